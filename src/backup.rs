@@ -0,0 +1,1052 @@
+use anyhow::{anyhow, bail, Context};
+use core::{
+    fmt,
+    fmt::{Display, Formatter},
+};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io,
+    path::{self, Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use walkdir::DirEntry;
+
+use crate::{
+    compress::{compress_file, open_copy, should_compress, COMPRESSED_EXTENSION},
+    config::{DestErrors, DiffBy, IllegalNames, Layout, ScanOptions, SizeFormat},
+    dedup::ContentIndex,
+    hash::{hash_file, hash_reader},
+    manifest::ManifestEntry,
+    parallel::with_timeout,
+    platform::{
+        device_id, free_space, is_locked, list_ads, read_permissions, snapshot_path, stream_path,
+        UnixPermissions,
+    },
+    stats::Throughput,
+    FileClassification,
+};
+
+/// Computes where `source` ends up below `dest`.
+///
+/// The source path is recreated relative to the destination with the drive
+/// prefix turned into a plain folder, e.g. `C:\users\me\.env` becomes
+/// `<dest>\C\users\me\.env`. The `by-category` layout inserts the category
+/// folder in front of that. Names the destination can't take are replaced,
+/// see [`legal_name`].
+pub fn dest_path_for(
+    config: &ScanOptions,
+    dest: &Path,
+    source: &Path,
+    class: &FileClassification,
+) -> PathBuf {
+    let mut target = dest.to_path_buf();
+
+    if config.layout == Layout::ByCategory {
+        if let Some(category) = class.category() {
+            target.push(config.category_dir(category));
+        }
+    }
+
+    for component in source.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                let drive = prefix.as_os_str().to_string_lossy();
+                target.push(drive.trim_end_matches(':'));
+            }
+            Component::Normal(part) => match legal_name(part, config.windows_names) {
+                Some(name) => target.push(name),
+                None => target.push(part),
+            },
+            Component::RootDir | Component::CurDir | Component::ParentDir => {}
+        }
+    }
+
+    target
+}
+
+/// Longest name most filesystems take, in bytes on Linux and macOS and in
+/// UTF-16 units on Windows.
+const MAX_NAME_LEN: usize = 255;
+
+/// Characters Windows doesn't allow in names, besides control characters.
+const WINDOWS_ILLEGAL: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names Windows reserves, with or without an extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// The name a path component gets below the destination, or `None` if it
+/// can be used as is.
+///
+/// Names longer than [`MAX_NAME_LEN`] are shortened, keeping the extension
+/// and ending the stem in a hash of the full name so shortened siblings stay
+/// apart, e.g. `very long name~1a2b3c4d.txt`. With `windows` set, illegal
+/// characters become `_`, trailing dots and spaces, which Windows drops,
+/// end in `_` and reserved device names like `CON.txt` get a leading `_`.
+fn legal_name(name: &OsStr, windows: bool) -> Option<String> {
+    if !windows && name.len() <= MAX_NAME_LEN {
+        return None;
+    }
+
+    let mut legal = name.to_string_lossy().into_owned();
+    if windows {
+        legal = legal
+            .chars()
+            .map(|c| {
+                if c < ' ' || WINDOWS_ILLEGAL.contains(&c) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let trimmed = legal.trim_end_matches(['.', ' ']).len();
+        if trimmed < legal.len() {
+            legal.truncate(trimmed);
+            legal.push('_');
+        }
+        let device = legal.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED.contains(&device.to_ascii_lowercase().as_str()) {
+            legal.insert(0, '_');
+        }
+    }
+    if name_len(&legal, windows) > MAX_NAME_LEN {
+        legal = shorten_name(&legal, name, windows);
+    }
+
+    (OsStr::new(&legal) != name).then_some(legal)
+}
+
+fn name_len(name: &str, windows: bool) -> usize {
+    if windows {
+        name.encode_utf16().count()
+    } else {
+        name.len()
+    }
+}
+
+/// Cuts `name` down to [`MAX_NAME_LEN`], see [`legal_name`]. The hash is
+/// of `original`, before any characters were replaced.
+fn shorten_name(name: &str, original: &OsStr, windows: bool) -> String {
+    let hash = format!("~{:08x}", crc32fast::hash(original.as_encoded_bytes()));
+    // only short extensions are worth keeping, anything longer is more
+    // likely part of the name
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() <= 16 => {
+            (stem, &name[stem.len()..])
+        }
+        _ => (name, ""),
+    };
+
+    let budget = MAX_NAME_LEN - name_len(&hash, windows) - name_len(extension, windows);
+    let mut short = String::new();
+    for c in stem.chars() {
+        let len = if windows { c.len_utf16() } else { c.len_utf8() };
+        if name_len(&short, windows) + len > budget {
+            break;
+        }
+        short.push(c);
+    }
+    short + &hash + extension
+}
+
+/// Fails for a source with a name the destination can't take, unless
+/// `--on-illegal-name` has it sanitized.
+fn check_name(config: &ScanOptions, source: &Path) -> Result<(), BackupError> {
+    if config.on_illegal_name == IllegalNames::Sanitize {
+        return Ok(());
+    }
+    let Some(name) = source.components().find_map(|component| match component {
+        Component::Normal(part) if legal_name(part, config.windows_names).is_some() => Some(part),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+
+    let err = anyhow!(
+        "the destination can't take the name `{}` in `{}`",
+        name.to_string_lossy(),
+        source.display()
+    );
+    Err(match config.on_illegal_name {
+        IllegalNames::Skip => BackupError::Skipped(err),
+        _ => BackupError::Destination(err),
+    })
+}
+
+/// Why a file didn't make it into the destination.
+#[derive(Debug)]
+pub enum BackupError {
+    /// The source couldn't be read. Only this file is lost, so the scan can
+    /// go on.
+    Skipped(anyhow::Error),
+    /// Another process holds the source open exclusively. Like
+    /// [`Self::Skipped`], but recorded in the manifest so it can be retried
+    /// once the file is free. Carries the source as the manifest stores it.
+    Locked(PathBuf),
+    /// The destination is unusable or full, so later files would fail the
+    /// same way.
+    Destination(anyhow::Error),
+    /// Writing the copy failed and `--on-dest-error skip` let the run go
+    /// on. Carries the source as the manifest stores it.
+    Failed(PathBuf, anyhow::Error),
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Skipped(err) | Self::Destination(err) | Self::Failed(_, err) => {
+                write!(f, "{:#}", err)
+            }
+            Self::Locked(source) => {
+                write!(f, "`{}` is locked by another process", source.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+/// Copies a classified file into the destination, if one is configured,
+/// returning its manifest entry. Time spent and bytes moved are added to
+/// `throughput`.
+pub fn backup_entry(
+    config: &ScanOptions,
+    entry: &DirEntry,
+    class: &FileClassification,
+    index: Option<&ContentIndex>,
+    throughput: &mut Throughput,
+) -> Result<Option<ManifestEntry>, BackupError> {
+    let Some(dest) = config.dest.as_deref() else {
+        return Ok(None);
+    };
+    check_name(config, entry.path())?;
+
+    with_dest_policy(config, entry.path(), || {
+        copy_entry(config, dest, entry, class, index, throughput)
+    })
+}
+
+/// The copy of [`backup_entry`], up to the first error.
+fn copy_entry(
+    config: &ScanOptions,
+    dest: &Path,
+    entry: &DirEntry,
+    class: &FileClassification,
+    index: Option<&ContentIndex>,
+    throughput: &mut Throughput,
+) -> Result<Option<ManifestEntry>, BackupError> {
+    // hashed up front so an unreadable source is told apart from a failing
+    // destination below
+    let start = Instant::now();
+    let (source, algo) = (snapshot_path(entry.path()), config.hash_algo);
+    let hashed = read_within(config, move || hash_file(&source, algo));
+    throughput.hash += start.elapsed();
+    let hash = match hashed {
+        Ok(hash) => hash,
+        Err(err) if is_locked(&err) => {
+            return Err(BackupError::Locked(manifest_source(config, entry.path())))
+        }
+        Err(err) => {
+            return Err(BackupError::Skipped(
+                anyhow::Error::new(err)
+                    .context(format!("failed to read `{}`", entry.path().display())),
+            ))
+        }
+    };
+
+    if let Some(min_free) = config.min_free {
+        let size = entry.metadata().map_or(0, |metadata| metadata.len());
+        ensure_free_space(dest, entry.path(), size, min_free, config.sizes)
+            .map_err(BackupError::Destination)?;
+    }
+
+    let (target, compress) = copy_target(config, dest, entry.path(), class);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create `{}`", parent.display()))
+            .map_err(BackupError::Destination)?;
+    }
+
+    let start = Instant::now();
+    let linked = index.and_then(|index| link_duplicate(index, entry, &hash, compress, &target));
+    let size = match linked {
+        Some(size) => size,
+        None => {
+            let copied = write_atomically(&target, |temp| {
+                let (source, temp) = (snapshot_path(entry.path()), temp.to_path_buf());
+                read_within(config, move || match compress {
+                    Some(level) => compress_file(&source, &temp, level),
+                    None => fs::copy(&source, &temp),
+                })
+            });
+            let size = copied.map_err(|err| copy_error(err, entry.path(), &target))?;
+            throughput.bytes_read += size;
+            throughput.bytes_copied += size;
+            size
+        }
+    };
+    throughput.copy += start.elapsed();
+
+    let permissions = if config.preserve_permissions {
+        preserve_permissions(entry.path(), &target).map_err(BackupError::Destination)?
+    } else {
+        None
+    };
+
+    Ok(Some(ManifestEntry {
+        source: manifest_source(config, entry.path()),
+        path: target.strip_prefix(dest).unwrap_or(&target).to_path_buf(),
+        size,
+        hash,
+        tag: class.to_string(),
+        category: class.category().map(str::to_owned),
+        compressed: compress.is_some(),
+        permissions,
+    }))
+}
+
+/// Hard links `target` to the copy of an earlier file with the same
+/// contents, returning the size, or claims `target` as the copy of these
+/// contents and returns nothing if there is none yet. A link that can't be
+/// made, as the earlier copy failed or the destination has no hard links,
+/// leaves the file to be copied.
+fn link_duplicate(
+    index: &ContentIndex,
+    entry: &DirEntry,
+    hash: &str,
+    compress: Option<u32>,
+    target: &Path,
+) -> Option<u64> {
+    let size = entry.metadata().ok()?.len();
+    let drive = device_id(entry.path()).ok();
+    let (copy, from) = index.claim(size, hash, compress.is_some(), target, drive)?;
+    write_atomically(target, |temp| fs::hard_link(&copy, temp).map(|()| size)).ok()?;
+    index.record_link(size, from != drive);
+
+    Some(size)
+}
+
+/// Where [`backup_entry`] puts the copy of `source`, along with the gzip
+/// level if it gets compressed.
+fn copy_target(
+    config: &ScanOptions,
+    dest: &Path,
+    source: &Path,
+    class: &FileClassification,
+) -> (PathBuf, Option<u32>) {
+    let target = dest_path_for(config, dest, source, class);
+    let compress = config
+        .compress_level
+        .filter(|&level| level > 0 && should_compress(class));
+    let target = match compress {
+        Some(_) => {
+            let mut compressed = target.into_os_string();
+            compressed.push(".");
+            compressed.push(COMPRESSED_EXTENSION);
+            PathBuf::from(compressed)
+        }
+        None => target,
+    };
+
+    (target, compress)
+}
+
+/// How a source compares with what the destination already holds at the
+/// place its copy would go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DestStatus {
+    /// Nothing there yet.
+    Create,
+    /// The existing copy matches the source.
+    Identical,
+    /// Something different is there and would be replaced.
+    Overwrite,
+}
+
+/// Compares a classified file with the copy [`backup_entry`] would
+/// overwrite, without writing anything. Returns `None` without a
+/// destination.
+pub fn diff_entry(
+    config: &ScanOptions,
+    entry: &DirEntry,
+    class: &FileClassification,
+    by: DiffBy,
+) -> anyhow::Result<Option<(PathBuf, DestStatus)>> {
+    let Some(dest) = config.dest.as_deref() else {
+        return Ok(None);
+    };
+
+    let (target, compress) = copy_target(config, dest, entry.path(), class);
+    let existing = match fs::metadata(&target) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some((target, DestStatus::Create)))
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read `{}`", target.display()))
+        }
+    };
+    let source = entry
+        .metadata()
+        .with_context(|| format!("failed to read `{}`", entry.path().display()))?;
+
+    // a gzipped copy's own size says little, so compare what it holds
+    let copy_size = || -> anyhow::Result<u64> {
+        if compress.is_none() {
+            return Ok(existing.len());
+        }
+        let mut copy = open_copy(&target, true)
+            .with_context(|| format!("failed to open `{}`", target.display()))?;
+        io::copy(&mut copy, &mut io::sink())
+            .with_context(|| format!("failed to read `{}`", target.display()))
+    };
+    let identical = match by {
+        DiffBy::Size => copy_size()? == source.len(),
+        DiffBy::Mtime => {
+            copy_size()? == source.len()
+                && match (existing.modified(), source.modified()) {
+                    (Ok(copy), Ok(source)) => copy >= source,
+                    _ => false,
+                }
+        }
+        DiffBy::Hash => {
+            let copy = open_copy(&target, compress.is_some())
+                .and_then(|copy| hash_reader(copy, config.hash_algo))
+                .with_context(|| format!("failed to read `{}`", target.display()))?;
+            let source = hash_file(entry.path(), config.hash_algo)
+                .with_context(|| format!("failed to read `{}`", entry.path().display()))?;
+            copy == source
+        }
+    };
+
+    let status = if identical {
+        DestStatus::Identical
+    } else {
+        DestStatus::Overwrite
+    };
+    Ok(Some((target, status)))
+}
+
+/// Copies the alternate data streams of a file into sidecar files next to
+/// where [`backup_entry`] put it, returning their manifest entries.
+///
+/// A sidecar is named after the file and the stream, e.g. the
+/// `Zone.Identifier` of `setup.exe` becomes `setup.exe.Zone.Identifier.ads`.
+/// Its manifest source is the stream itself, `setup.exe:Zone.Identifier`,
+/// so restoring onto NTFS puts it back as a stream.
+pub fn backup_streams(
+    config: &ScanOptions,
+    entry: &DirEntry,
+    class: &FileClassification,
+    throughput: &mut Throughput,
+) -> Result<Vec<ManifestEntry>, BackupError> {
+    let Some(dest) = config.dest.as_deref() else {
+        return Ok(Vec::new());
+    };
+    check_name(config, entry.path())?;
+
+    with_dest_policy(config, entry.path(), || {
+        copy_streams(config, dest, entry, class, throughput)
+    })
+}
+
+/// The copies of [`backup_streams`], up to the first error.
+fn copy_streams(
+    config: &ScanOptions,
+    dest: &Path,
+    entry: &DirEntry,
+    class: &FileClassification,
+    throughput: &mut Throughput,
+) -> Result<Vec<ManifestEntry>, BackupError> {
+    let target = dest_path_for(config, dest, entry.path(), class);
+    let mut streams = Vec::new();
+    for (name, size) in list_ads(entry.path()) {
+        let source = stream_path(entry.path(), &name);
+        let start = Instant::now();
+        let (path, algo) = (snapshot_path(&source), config.hash_algo);
+        let hashed = read_within(config, move || hash_file(&path, algo));
+        throughput.hash += start.elapsed();
+        let hash = match hashed {
+            Ok(hash) => hash,
+            Err(err) if is_locked(&err) => {
+                return Err(BackupError::Locked(manifest_source(config, &source)))
+            }
+            Err(err) => {
+                return Err(BackupError::Skipped(
+                    anyhow::Error::new(err)
+                        .context(format!("failed to read `{}`", source.display())),
+                ))
+            }
+        };
+
+        if let Some(min_free) = config.min_free {
+            ensure_free_space(dest, &source, size, min_free, config.sizes)
+                .map_err(BackupError::Destination)?;
+        }
+
+        let mut sidecar = target.clone().into_os_string();
+        sidecar.push(format!(".{}.ads", sanitize_stream_name(&name)));
+        let sidecar = PathBuf::from(sidecar);
+        let start = Instant::now();
+        let copied = write_atomically(&sidecar, |temp| {
+            let (source, temp) = (snapshot_path(&source), temp.to_path_buf());
+            read_within(config, move || copy_stream(&source, &temp))
+        });
+        throughput.copy += start.elapsed();
+        let size = copied.map_err(|err| copy_error(err, &source, &sidecar))?;
+        throughput.bytes_read += size;
+        throughput.bytes_copied += size;
+
+        streams.push(ManifestEntry {
+            source: manifest_source(config, &source),
+            path: sidecar.strip_prefix(dest).unwrap_or(&sidecar).to_path_buf(),
+            size,
+            hash,
+            tag: format!("stream {}", name),
+            category: class.category().map(str::to_owned),
+            compressed: false,
+            permissions: None,
+        });
+    }
+
+    Ok(streams)
+}
+
+/// Times a copy is tried again under `--on-dest-error retry`.
+const DEST_RETRIES: u32 = 3;
+/// Wait before the first retry, doubled for each one after it.
+const DEST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Applies `--on-dest-error` to the destination errors of `backup`, the
+/// copy of `source`: retrying it with a growing wait in between, turning
+/// the error into [`BackupError::Failed`], or passing it on to stop the
+/// run.
+fn with_dest_policy<T>(
+    config: &ScanOptions,
+    source: &Path,
+    mut backup: impl FnMut() -> Result<T, BackupError>,
+) -> Result<T, BackupError> {
+    let mut delay = DEST_RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match (backup(), config.on_dest_error) {
+            (Err(BackupError::Destination(err)), DestErrors::Retry) if retries < DEST_RETRIES => {
+                eprintln!("warning: {:#}, retrying in {:?}", err, delay);
+                thread::sleep(delay);
+                delay *= 2;
+                retries += 1;
+            }
+            (Err(BackupError::Destination(err)), DestErrors::Skip) => {
+                return Err(BackupError::Failed(manifest_source(config, source), err))
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+/// Runs a read of source contents under `--file-timeout`, failing with
+/// [`io::ErrorKind::TimedOut`] if it doesn't finish in time.
+fn read_within<R: Send + 'static>(
+    config: &ScanOptions,
+    read: impl FnOnce() -> io::Result<R> + Send + 'static,
+) -> io::Result<R> {
+    with_timeout(config.file_timeout, read).unwrap_or_else(|| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "timed out after {:?}",
+                config.file_timeout.unwrap_or_default()
+            ),
+        ))
+    })
+}
+
+/// Sorts out a failed copy of `source` to `target`. A copy that timed out
+/// hung on the source, so only that file is lost; anything else is blamed
+/// on the destination.
+fn copy_error(err: io::Error, source: &Path, target: &Path) -> BackupError {
+    if err.kind() == io::ErrorKind::TimedOut {
+        return BackupError::Skipped(
+            anyhow::Error::new(err).context(format!("failed to read `{}`", source.display())),
+        );
+    }
+    BackupError::Destination(anyhow::Error::new(err).context(format!(
+        "failed to copy `{}` to `{}`",
+        source.display(),
+        target.display()
+    )))
+}
+
+/// Writes `target` through `write`, which is handed a temporary sibling
+/// named `.<name>.tmp-<random>` that is renamed into place once it is
+/// complete. An interrupted or failed copy thus never leaves a partial file
+/// under the final name for `--verify` or a later `--diff-dest` to mistake
+/// for a real one; the temporary file is removed on failure.
+fn write_atomically(
+    target: &Path,
+    write: impl FnOnce(&Path) -> io::Result<u64>,
+) -> io::Result<u64> {
+    let temp = temp_path_for(target);
+    let written = write(&temp).and_then(|size| fs::rename(&temp, target).map(|()| size));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Most bytes of the target's name kept in its temporary name, leaving
+/// room for the suffix within the 255 filesystems allow for a name.
+const TEMP_NAME_PREFIX: usize = 200;
+
+/// A name next to `target` that no other copy, in this run or a concurrent
+/// one, is writing to. Only the start of a long name is kept, so targets
+/// named close to the limit still get one.
+fn temp_path_for(target: &Path) -> PathBuf {
+    static COPIES: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_nanos());
+    let unique = COPIES.fetch_add(1, Ordering::Relaxed);
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let mut end = name.len().min(TEMP_NAME_PREFIX);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    target.with_file_name(format!(
+        ".{}.tmp-{:x}{:08x}{:x}",
+        &name[..end],
+        process::id(),
+        nanos,
+        unique
+    ))
+}
+
+/// Gives the copy the source's mode, which compressed copies would
+/// otherwise lose, and returns it for the manifest. The owner is left to
+/// the restore.
+fn preserve_permissions(source: &Path, copy: &Path) -> anyhow::Result<Option<UnixPermissions>> {
+    let Some(permissions) = read_permissions(source)
+        .with_context(|| format!("failed to read permissions of `{}`", source.display()))?
+    else {
+        return Ok(None);
+    };
+    fs::set_permissions(copy, fs::metadata(source)?.permissions())
+        .with_context(|| format!("failed to set permissions of `{}`", copy.display()))?;
+
+    Ok(Some(permissions))
+}
+
+/// Stream names may hold characters Windows doesn't allow in file names.
+fn sanitize_stream_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '<' | '>' | '"' | '|' | '?' | '*' | '\\' | '/' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Copies the contents of `source` into a new file. Unlike [`fs::copy`] this
+/// reads a single stream and doesn't carry the file's other streams along.
+fn copy_stream(source: &Path, target: &Path) -> io::Result<u64> {
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(target)?;
+    io::copy(&mut reader, &mut writer)
+}
+
+/// The source path as recorded in the manifest: relative to `--relative-to`
+/// when below it, absolute otherwise.
+fn manifest_source(config: &ScanOptions, source: &Path) -> PathBuf {
+    let Some(root) = &config.relative_to else {
+        return source.to_path_buf();
+    };
+    let source = path::absolute(source).unwrap_or_else(|_| source.to_path_buf());
+    match source.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => source,
+    }
+}
+
+fn ensure_free_space(
+    dest: &Path,
+    source: &Path,
+    size: u64,
+    min_free: u64,
+    sizes: SizeFormat,
+) -> anyhow::Result<()> {
+    let free = free_space(dest)?;
+
+    if free.saturating_sub(size) < min_free {
+        bail!(
+            "not enough space left on `{}` to copy `{}`: {} free, {} needed plus {} to keep free",
+            dest.display(),
+            source.display(),
+            sizes.describe(free),
+            sizes.amount(size),
+            sizes.amount(min_free)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{entry, file_class, TempTree},
+        SecretFileType,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn by_category_layout_puts_files_in_their_mapped_folder() {
+        let config = ScanOptions {
+            layout: Layout::ByCategory,
+            category_dirs: HashMap::from([("secrets".to_owned(), "sensitive/keys".to_owned())]),
+            ..ScanOptions::default()
+        };
+        let class = FileClassification::Secret(SecretFileType::Env);
+        let target = dest_path_for(
+            &config,
+            Path::new("/backup"),
+            Path::new("/home/me/.env"),
+            &class,
+        );
+        assert_eq!(target, Path::new("/backup/sensitive/keys/home/me/.env"));
+
+        let mirrored = dest_path_for(
+            &ScanOptions::default(),
+            Path::new("/backup"),
+            Path::new("/home/me/.env"),
+            &class,
+        );
+        assert_eq!(mirrored, Path::new("/backup/home/me/.env"));
+    }
+
+    #[test]
+    fn copies_are_refused_once_they_would_eat_into_the_reserve() {
+        let tree = TempTree::new();
+        let source = tree.file("src/.env", "KEY=value\n");
+        let dest = tree.path().join("dest");
+        // the reserve is set relative to what the volume really has, which
+        // stands in for a nearly full destination
+        let free = free_space(&dest).unwrap();
+        let backup = |min_free| {
+            let config = ScanOptions {
+                dest: Some(dest.clone()),
+                min_free: Some(min_free),
+                ..ScanOptions::default()
+            };
+            backup_entry(
+                &config,
+                &entry(&source),
+                &FileClassification::Secret(SecretFileType::Env),
+                None,
+                &mut Throughput::default(),
+            )
+        };
+
+        let refused = backup(free.saturating_add(1));
+        assert!(matches!(refused, Err(BackupError::Destination(_))));
+        assert!(!dest.exists());
+        assert!(matches!(backup(0), Ok(Some(_))));
+    }
+
+    #[test]
+    fn the_size_of_the_copy_counts_against_the_reserve() {
+        let tree = TempTree::new();
+        let free = free_space(tree.path()).unwrap();
+        let source = Path::new("big.iso");
+        let sizes = SizeFormat::Bytes;
+        assert!(ensure_free_space(tree.path(), source, 0, free / 2, sizes).is_ok());
+        let error = ensure_free_space(tree.path(), source, free, 1, sizes).unwrap_err();
+        assert!(error.to_string().starts_with("not enough space left"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sources_outside_the_relative_root_stay_absolute() {
+        let config = ScanOptions {
+            relative_to: Some(PathBuf::from("/home/me")),
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            manifest_source(&config, Path::new("/home/me/docs/a.txt")),
+            Path::new("docs/a.txt")
+        );
+        assert_eq!(
+            manifest_source(&config, Path::new("/mnt/usb/a.txt")),
+            Path::new("/mnt/usb/a.txt")
+        );
+        assert_eq!(
+            manifest_source(&ScanOptions::default(), Path::new("/home/me/a.txt")),
+            Path::new("/home/me/a.txt")
+        );
+    }
+
+    #[test]
+    fn stream_names_become_valid_file_names() {
+        assert_eq!(sanitize_stream_name("Zone.Identifier"), "Zone.Identifier");
+        assert_eq!(sanitize_stream_name("a:b/c*\u{1}"), "a_b_c__");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn streams_are_copied_into_sidecars() {
+        let tree = TempTree::new();
+        let source = tree.file("src/setup.exe", "MZ");
+        fs::write(stream_path(&source, "Zone.Identifier"), "ZoneId=3").unwrap();
+        let dest = tree.path().join("dest");
+        let config = ScanOptions {
+            dest: Some(dest.clone()),
+            ..ScanOptions::default()
+        };
+        let class = FileClassification::Regular;
+
+        let streams =
+            backup_streams(&config, &entry(&source), &class, &mut Throughput::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].source, stream_path(&source, "Zone.Identifier"));
+        let mut sidecar = dest_path_for(&config, &dest, &source, &class).into_os_string();
+        sidecar.push(".Zone.Identifier.ads");
+        assert_eq!(fs::read_to_string(sidecar).unwrap(), "ZoneId=3");
+    }
+
+    #[test]
+    fn only_compressible_copies_get_gzipped() {
+        let tree = TempTree::new();
+        let dest = tree.path().join("dest");
+        let config = |compress_level| ScanOptions {
+            dest: Some(dest.clone()),
+            compress_level,
+            ..ScanOptions::default()
+        };
+        let env = tree.file(".env", "KEY=value\n");
+        let zip = tree.zip("photos.zip", &["a.jpg"]);
+        let target = |level, path: &Path| {
+            let (target, compress) = copy_target(&config(level), &dest, path, &file_class(path));
+            (target.to_string_lossy().ends_with(".gz"), compress)
+        };
+
+        assert_eq!(target(Some(6), &env), (true, Some(6)));
+        assert_eq!(target(Some(6), &zip), (false, None));
+        assert_eq!(target(Some(0), &env), (false, None));
+        assert_eq!(target(None, &env), (false, None));
+    }
+
+    #[test]
+    fn aborted_copies_leave_nothing_behind() {
+        let tree = TempTree::new();
+        let dir = tree.dir("dest");
+        let target = dir.join("report.xlsx");
+        let names = || {
+            let mut names = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let aborted = write_atomically(&target, |temp| {
+            fs::write(temp, "half of it")?;
+            Err(io::Error::other("interrupted"))
+        });
+        assert!(aborted.is_err());
+        assert!(names().is_empty());
+
+        // an earlier copy stays as it was
+        fs::write(&target, "complete").unwrap();
+        assert!(write_atomically(&target, |temp| {
+            fs::write(temp, "half")?;
+            Err(io::Error::other("interrupted"))
+        })
+        .is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "complete");
+        assert_eq!(names(), ["report.xlsx"]);
+
+        let written = write_atomically(&target, |temp| {
+            // nothing shows up under the final name until the end
+            assert_ne!(temp, target);
+            assert_eq!(fs::read_to_string(&target).unwrap(), "complete");
+            fs::write(temp, "replaced")?;
+            Ok(8)
+        });
+        assert_eq!(written.unwrap(), 8);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "replaced");
+        assert_eq!(names(), ["report.xlsx"]);
+    }
+
+    #[test]
+    fn temporary_names_are_unique_siblings() {
+        let target = Path::new("dest/report.xlsx");
+        let (a, b) = (temp_path_for(target), temp_path_for(target));
+        assert_ne!(a, b);
+        for temp in [a, b] {
+            assert_eq!(temp.parent(), target.parent());
+            let name = temp.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with(".report.xlsx.tmp-"), "{name}");
+        }
+    }
+
+    #[test]
+    fn targets_named_near_the_limit_get_a_temporary_name() {
+        let tree = TempTree::new();
+        let name = format!("{}.gpg", "a".repeat(246));
+        assert_eq!(name.len(), 250);
+        let target = tree.path().join(&name);
+
+        let temp = temp_path_for(&target);
+        assert!(temp.file_name().unwrap().len() <= 255);
+        let written = write_atomically(&target, |temp| {
+            fs::write(temp, "ciphertext")?;
+            Ok(10)
+        });
+        assert_eq!(written.unwrap(), 10);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "ciphertext");
+
+        // cut inside a character, the name ends before it
+        let wide = tree.path().join(format!("a{}", "é".repeat(120)));
+        let temp = temp_path_for(&wide);
+        let temp = temp.file_name().unwrap().to_str().unwrap();
+        assert!(
+            temp.starts_with(&format!(".a{}.tmp-", "é".repeat(99))),
+            "{temp}"
+        );
+    }
+
+    #[test]
+    fn names_windows_refuses_are_rewritten() {
+        let legal = |name: &str| legal_name(OsStr::new(name), true);
+        assert_eq!(
+            legal("notes: draft?.txt").as_deref(),
+            Some("notes_ draft_.txt")
+        );
+        assert_eq!(legal("a<b>|c\"*\u{7}.md").as_deref(), Some("a_b__c___.md"));
+        assert_eq!(legal("CON.txt").as_deref(), Some("_CON.txt"));
+        assert_eq!(legal("lpt1").as_deref(), Some("_lpt1"));
+        assert_eq!(legal("trailing. .").as_deref(), Some("trailing_"));
+        assert_eq!(legal("console.txt"), None);
+        assert_eq!(legal("plain name.txt"), None);
+        // elsewhere only the length counts
+        assert_eq!(legal_name(OsStr::new("notes: draft?.txt"), false), None);
+    }
+
+    #[test]
+    fn overlong_names_are_shortened_apart() {
+        let long = |stem: &str| format!("{}.txt", stem.repeat(300));
+        let a = legal_name(OsStr::new(&long("a")), false).unwrap();
+        let b = legal_name(OsStr::new(&(long("a") + ".txt")), false).unwrap();
+        assert_eq!(a.len(), MAX_NAME_LEN);
+        assert!(a.ends_with(".txt") && a.contains('~'), "{}", a);
+        assert_ne!(a, b);
+        assert_eq!(
+            legal_name(OsStr::new(&"a".repeat(MAX_NAME_LEN)), false),
+            None
+        );
+
+        // Windows counts UTF-16 units, not bytes
+        let accented = "é".repeat(200);
+        assert_eq!(legal_name(OsStr::new(&accented), true), None);
+        let short = legal_name(OsStr::new(&accented), false).unwrap();
+        assert!(short.len() <= MAX_NAME_LEN);
+        assert!(short.starts_with("éé"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn illegal_names_are_copied_skipped_or_refused_as_configured() {
+        let tree = TempTree::new();
+        let source = tree.file("src/notes: draft?.txt", "KEY=value\n");
+        let dest = tree.path().join("dest");
+        let backup = |on_illegal_name| {
+            let config = ScanOptions {
+                dest: Some(dest.clone()),
+                windows_names: true,
+                on_illegal_name,
+                ..ScanOptions::default()
+            };
+            backup_entry(
+                &config,
+                &entry(&source),
+                &FileClassification::Secret(SecretFileType::Env),
+                None,
+                &mut Throughput::default(),
+            )
+        };
+
+        assert!(matches!(
+            backup(IllegalNames::Skip),
+            Err(BackupError::Skipped(_))
+        ));
+        assert!(matches!(
+            backup(IllegalNames::Error),
+            Err(BackupError::Destination(_))
+        ));
+        assert!(!dest.exists());
+
+        // the manifest keeps the name the copy had to give up
+        let copied = backup(IllegalNames::Sanitize).unwrap().unwrap();
+        assert_eq!(copied.source, source);
+        assert_eq!(copied.path.file_name().unwrap(), "notes_ draft_.txt");
+        assert!(dest.join(&copied.path).is_file());
+    }
+
+    #[test]
+    fn destination_errors_follow_the_policy() {
+        let policy = |on_dest_error, failures: u32| {
+            let config = ScanOptions {
+                on_dest_error,
+                ..ScanOptions::default()
+            };
+            let mut attempts = 0;
+            let result = with_dest_policy(&config, Path::new("/src/notes.txt"), || {
+                attempts += 1;
+                if attempts > failures {
+                    Ok(attempts)
+                } else {
+                    Err(BackupError::Destination(anyhow::anyhow!("disk full")))
+                }
+            });
+            (result, attempts)
+        };
+
+        assert!(matches!(
+            policy(DestErrors::Abort, 1),
+            (Err(BackupError::Destination(_)), 1)
+        ));
+        let (skipped, attempts) = policy(DestErrors::Skip, 1);
+        let Err(BackupError::Failed(source, err)) = skipped else {
+            panic!("the copy is skipped");
+        };
+        assert_eq!(
+            (source.as_path(), attempts),
+            (Path::new("/src/notes.txt"), 1)
+        );
+        assert_eq!(err.to_string(), "disk full");
+        // waits a second before trying again
+        assert!(matches!(policy(DestErrors::Retry, 1), (Ok(2), 2)));
+        // errors of the source are never retried or skipped
+        let mut attempts = 0;
+        let config = ScanOptions {
+            on_dest_error: DestErrors::Retry,
+            ..ScanOptions::default()
+        };
+        let unreadable = with_dest_policy(&config, Path::new("/src/notes.txt"), || {
+            attempts += 1;
+            Err::<(), _>(BackupError::Skipped(anyhow::anyhow!("unreadable")))
+        });
+        assert!(matches!(unreadable, Err(BackupError::Skipped(_))));
+        assert_eq!(attempts, 1);
+    }
+}
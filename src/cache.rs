@@ -0,0 +1,279 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::SystemTime,
+};
+use walkdir::DirEntry;
+
+use crate::{
+    classify_entry, config::ScanOptions, rules::classify_custom, EntryClassification,
+    FileClassification,
+};
+
+/// Changed whenever a release classifies some files differently, so that
+/// results of an older build aren't reused.
+const CLASSIFIER_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime: SystemTime,
+    class: FileClassification,
+}
+
+/// What is stored on disk. Files from before the header existed lack the
+/// fields and come out as version 0, so they are dropped like any other
+/// outdated cache.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CacheFile {
+    version: u32,
+    /// [`ScanOptions::classifier_fingerprint`] of the run that wrote it.
+    options: String,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// [`CacheFile`] as it is written, borrowing the entries.
+#[derive(Serialize)]
+struct SavedCache<'a> {
+    version: u32,
+    options: &'a str,
+    entries: &'a HashMap<String, CacheEntry>,
+}
+
+/// Classifications that required reading file contents, remembered across
+/// runs so unchanged files don't have to be read again.
+///
+/// Entries are keyed by path and only reused while the file's size and
+/// modification time match what was recorded. The whole cache is dropped
+/// when it was written by another classifier version or with options that
+/// change how files are classified.
+#[derive(Default)]
+pub struct ClassifyCache {
+    path: Option<PathBuf>,
+    options: String,
+    /// Locked only around lookups and updates, never while classifying, so
+    /// parallel workers can share the cache.
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    dirty: AtomicBool,
+}
+
+impl ClassifyCache {
+    /// A cache that remembers nothing, used for `--no-cache`.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Loads the cache stored at `path` for a run with `config`, starting
+    /// out empty if there is none or it doesn't fit the run.
+    pub fn load(path: &Path, config: &ScanOptions) -> anyhow::Result<Self> {
+        let file: CacheFile = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("failed to parse cache `{}`", path.display()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => CacheFile::default(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read cache `{}`", path.display()))
+            }
+        };
+
+        let options = config.classifier_fingerprint();
+        let fits = file.version == CLASSIFIER_VERSION && file.options == options;
+        // an outdated file is replaced even if nothing gets cached
+        let outdated = !fits && !file.entries.is_empty();
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            options,
+            entries: Mutex::new(if fits { file.entries } else { HashMap::new() }),
+            dirty: AtomicBool::new(outdated),
+        })
+    }
+
+    /// Classifies `entry`, reusing the cached result if the file is unchanged
+    /// since it was recorded.
+    pub fn classify(&self, entry: &DirEntry, config: &ScanOptions) -> EntryClassification {
+        // user rules win over anything remembered from a previous run
+        if self.path.is_none()
+            || entry.file_type().is_dir()
+            || classify_custom(entry.path(), !config.classify_only).is_some()
+        {
+            return classify_entry(entry, config);
+        }
+
+        let (Some(key), Some((size, mtime))) = (entry.path().to_str(), file_stamp(entry)) else {
+            return classify_entry(entry, config);
+        };
+
+        if let Some(cached) = self.entries().get(key) {
+            if cached.size == size && cached.mtime == mtime {
+                return EntryClassification::File(cached.class.clone());
+            }
+        }
+
+        let classification = classify_entry(entry, config);
+        match &classification {
+            EntryClassification::File(class) if class.is_content_based() => {
+                self.entries().insert(
+                    key.to_owned(),
+                    CacheEntry {
+                        size,
+                        mtime,
+                        class: class.clone(),
+                    },
+                );
+                self.dirty.store(true, Ordering::Relaxed);
+            }
+            // a file that stopped being content-based must not keep its
+            // stale entry around
+            _ => {
+                if self.entries().remove(key).is_some() {
+                    self.dirty.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+
+        classification
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, CacheEntry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Writes the cache back to disk if anything changed.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        let Some(path) = self.path.as_deref().filter(|_| dirty) else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create `{}`", parent.display()))?;
+        }
+
+        // write next to the cache and rename so an interrupted save never
+        // leaves a truncated cache behind
+        let tmp = path.with_extension("json.tmp");
+        let file = SavedCache {
+            version: CLASSIFIER_VERSION,
+            options: &self.options,
+            entries: &self.entries(),
+        };
+        fs::write(&tmp, serde_json::to_vec(&file)?)
+            .with_context(|| format!("failed to write cache `{}`", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("failed to write cache `{}`", path.display()))?;
+
+        Ok(())
+    }
+}
+
+fn file_stamp(entry: &DirEntry) -> Option<(u64, SystemTime)> {
+    let metadata = entry.metadata().ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, TempTree};
+    use std::fs::File;
+
+    /// Rewrites `path` with other contents of the same length, keeping its
+    /// modification time, so only a cached result can stay the same.
+    fn rewrite_unnoticed(path: &Path, contents: &str) {
+        let mtime = fs::metadata(path).unwrap().modified().unwrap();
+        fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn classify_with(cache_path: &Path, config: &ScanOptions, path: &Path) -> String {
+        let cache = ClassifyCache::load(cache_path, config).unwrap();
+        let tag = cache.classify(&entry(path), config).to_string();
+        cache.save().unwrap();
+        tag
+    }
+
+    #[test]
+    fn unchanged_files_reuse_the_cached_result() {
+        let tree = TempTree::new();
+        let cache = tree.path().join("cache.json");
+        let csv = tree.file("data.csv", "a,b,c\n1,2,3\n4,5,6\n");
+        let config = ScanOptions::default();
+
+        assert_eq!(classify_with(&cache, &config, &csv), "csv(',')");
+        rewrite_unnoticed(&csv, "a;b;c\n1;2;3\n4;5;6\n");
+        assert_eq!(classify_with(&cache, &config, &csv), "csv(',')");
+
+        // a changed size gives the change away
+        fs::write(&csv, "a;b\n1;2\n").unwrap();
+        assert_eq!(classify_with(&cache, &config, &csv), "csv(';')");
+    }
+
+    #[test]
+    fn cache_of_other_options_is_dropped() {
+        let tree = TempTree::new();
+        let cache = tree.path().join("cache.json");
+        let csv = tree.file("data.csv", "a,b,c\n1,2,3\n4,5,6\n");
+
+        assert_eq!(
+            classify_with(&cache, &ScanOptions::default(), &csv),
+            "csv(',')"
+        );
+        rewrite_unnoticed(&csv, "a;b;c\n1;2;3\n4;5;6\n");
+        let config = ScanOptions {
+            csv_skip_lines: 1,
+            ..ScanOptions::default()
+        };
+        assert_ne!(
+            config.classifier_fingerprint(),
+            ScanOptions::default().classifier_fingerprint()
+        );
+        let cache = ClassifyCache::load(&cache, &config).unwrap();
+        assert!(cache.entries().is_empty());
+    }
+
+    #[test]
+    fn cache_of_another_classifier_version_is_dropped() {
+        let tree = TempTree::new();
+        let csv = tree.file("data.csv", "a,b,c\n1,2,3\n");
+        let config = ScanOptions::default();
+        let (size, mtime) = file_stamp(&entry(&csv)).unwrap();
+        let stale = SavedCache {
+            version: CLASSIFIER_VERSION + 1,
+            options: &config.classifier_fingerprint(),
+            entries: &HashMap::from([(
+                csv.to_str().unwrap().to_owned(),
+                CacheEntry {
+                    size,
+                    mtime,
+                    class: FileClassification::Regular,
+                },
+            )]),
+        };
+        let cache = tree.file("cache.json", serde_json::to_vec(&stale).unwrap());
+        assert!(ClassifyCache::load(&cache, &config)
+            .unwrap()
+            .entries()
+            .is_empty());
+
+        // caches from before the header are dropped too
+        let old = tree.file("old.json", serde_json::to_vec(stale.entries).unwrap());
+        assert!(ClassifyCache::load(&old, &config)
+            .unwrap()
+            .entries()
+            .is_empty());
+    }
+}
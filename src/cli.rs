@@ -0,0 +1,873 @@
+use anyhow::Context;
+use clap::{ArgAction, Parser};
+use std::{
+    collections::HashMap,
+    fs,
+    num::NonZeroUsize,
+    path::{self, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compress::parse_compress_level,
+    config::{
+        load_config, parse_duration, parse_size, DestErrors, DiffBy, EmptyFiles, ExtensionOrder,
+        IllegalNames, LargeDirs, Layout, ManifestFormat, OutputFormat, ScanOptions, SizeFormat,
+        Traversal, DEFAULT_CSV_SAMPLE_BYTES, DEFAULT_DIR_SIZES_TOP, DEFAULT_PROGRESS_INTERVAL,
+    },
+    hash::HashAlgo,
+    platform::{has_windows_names, parse_reparse_tag, state_dir},
+    rules::{install_rules, load_rules},
+    template::Template,
+};
+
+/// Searches all disks for interesting files and optionally copies them out.
+///
+/// Defaults for most options can be kept in `config.toml` in the user's
+/// config directory; options given here take precedence.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    after_help = "Exit codes: 0 success, 1 error, 2 some files skipped because they \
+                  couldn't be read, 3 destination unusable or out of space, 4 cancelled \
+                  or out of time with a partial backup, 5 verification failed."
+)]
+pub struct Args {
+    /// Directories or drives to scan instead of all drives.
+    #[arg(value_name = "ROOT")]
+    roots: Vec<PathBuf>,
+
+    /// Read settings from this file instead of the default `config.toml`.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Copy every classified file below this directory.
+    #[arg(long, value_name = "DIR")]
+    dest: Option<PathBuf>,
+
+    /// How copied files are arranged below the destination [default: mirror].
+    #[arg(long, value_enum)]
+    layout: Option<Layout>,
+
+    /// Also write the manifest as CSV or SQLite next to `manifest.json`,
+    /// which is always written as the tool itself reads it [default: json].
+    #[arg(long, value_enum)]
+    manifest_format: Option<ManifestFormat>,
+
+    /// What to do with files whose names the destination doesn't allow,
+    /// like `a:b?.txt` copied onto Windows or a FAT or NTFS drive, or names
+    /// longer than 255 characters [default: sanitize].
+    #[arg(long, value_enum)]
+    on_illegal_name: Option<IllegalNames>,
+
+    /// What to do when writing a copy fails, as the destination is full,
+    /// read-only or gone. `retry` waits 1, 2 and 4 seconds between
+    /// attempts before giving up [default: abort].
+    #[arg(long, value_enum)]
+    on_dest_error: Option<DestErrors>,
+
+    /// Use a custom folder for a category in the `by-category` layout,
+    /// e.g. `secrets=sensitive`. May be given multiple times.
+    #[arg(long = "category-dir", value_name = "CATEGORY=DIR", value_parser = parse_key_value)]
+    category_dirs: Vec<(String, String)>,
+
+    /// Stay on the filesystem of each scanned root, like `find -xdev`.
+    #[arg(long)]
+    one_file_system: bool,
+
+    /// Show the label and filesystem of each scanned root's volume, e.g.
+    /// `D:\ # volume "Backup" (NTFS)`, and record them in the JSON output
+    /// and the manifest.
+    #[arg(long)]
+    drive_label: bool,
+
+    /// Also report and copy editor swap files, `.tmp` and `.part` files
+    /// and other temporary leftovers, which are skipped by default, along
+    /// with the backup copies `--skip-bak` skips.
+    #[arg(long)]
+    include_temp: bool,
+
+    /// Look inside `$Recycle.Bin` folders, which are skipped by default, and
+    /// classify deleted files by the names they had.
+    #[arg(long)]
+    include_recycle_bin: bool,
+
+    /// Skip `.bak`, `.old` and `file~` copies along with the temporary
+    /// files. Turn off if yours are deliberate backups, which are then
+    /// classified like the file they were made of [default: true].
+    #[arg(long, action = ArgAction::Set, value_name = "BOOL")]
+    skip_bak: Option<bool>,
+
+    /// Follow symbolic links while walking [default: true].
+    #[arg(long, action = ArgAction::Set, value_name = "BOOL")]
+    follow_links: Option<bool>,
+
+    /// Follow NTFS junctions while following links. Junctions usually
+    /// point into system locations, so this can be turned off separately
+    /// [default: true].
+    #[arg(long, action = ArgAction::Set, value_name = "BOOL")]
+    follow_junctions: Option<bool>,
+
+    /// Only walk into Windows reparse points of these kinds, skipping all
+    /// others, junctions and symlinks included. Takes `dedup`, `hsm`,
+    /// `hsm2`, `cloud`, `appexeclink`, `wof`, `wci`, `mount-point`,
+    /// `symlink` and `lx-symlink`, or tags as hex like `0x80000013`. E.g.
+    /// `dedup,wof` reads deduplicated and compressed files but leaves cloud
+    /// placeholders alone, which reading would download.
+    #[arg(
+        long,
+        value_name = "TAG,...",
+        value_delimiter = ',',
+        value_parser = parse_reparse_tag
+    )]
+    follow_reparse: Option<Vec<u32>>,
+
+    /// Don't follow links that only reach their target through more than N
+    /// links in a row. They are reported as unresolved instead of being
+    /// followed or copied.
+    #[arg(long, value_name = "N")]
+    max_symlink_depth: Option<usize>,
+
+    /// Cache content-based classifications in this file instead of the
+    /// default location in the user's cache directory.
+    #[arg(long, value_name = "PATH", conflicts_with = "no_cache")]
+    cache: Option<PathBuf>,
+
+    /// Don't read or write the classification cache.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Only consider files modified more recently than this reference
+    /// file, like `find -newer`.
+    #[arg(long, value_name = "FILE")]
+    newer_than: Option<PathBuf>,
+
+    /// Pick up an interrupted scan at PATH, given as it was printed: every
+    /// entry whose path sorts before it is walked past without being
+    /// reported or copied. Paths are compared component by component, and
+    /// each directory is listed in name order so that a depth-first walk
+    /// visits them in that same order run after run. Can't be combined with
+    /// `--sequential` or `--traversal bfs`, which visit entries in another
+    /// order.
+    #[arg(long, value_name = "PATH")]
+    resume_from: Option<PathBuf>,
+
+    /// Whether empty files are backed up, only reported, or skipped
+    /// [default: backup].
+    #[arg(long, value_enum, value_name = "POLICY")]
+    empty_files: Option<EmptyFiles>,
+
+    /// Load custom classification rules from a TOML file of `[[rule]]`
+    /// tables. They take precedence over the built-in classification.
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+
+    /// Show progress on stderr while scanning.
+    #[arg(long)]
+    progress: bool,
+
+    /// Count files before scanning so progress shows a total and an ETA.
+    /// Implies `--progress`.
+    #[arg(long)]
+    count_first: bool,
+
+    /// Append a line on how far the scan is to FILE every
+    /// `--progress-interval`: the time, files and bytes walked so far and
+    /// the entry being handled. For scheduled runs without a terminal to
+    /// draw progress on; works with or without `--progress`.
+    #[arg(long, value_name = "FILE")]
+    progress_to: Option<PathBuf>,
+
+    /// How often `--progress-to` writes a line, e.g. `30s` or `5m`.
+    /// [default: 10s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    progress_interval: Option<Duration>,
+
+    /// Report version control directories without descending into them.
+    /// Submodule and worktree `.git` files are still reported but not
+    /// copied.
+    #[arg(long)]
+    skip_vcs_internals: bool,
+
+    /// Abort before a copy would leave less than SIZE free on the
+    /// destination, e.g. `10G` or `500MB`.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_free: Option<u64>,
+
+    /// Read this much of a CSV file to detect its separator, e.g. `1MB`.
+    /// [default: 64KiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    csv_sample_bytes: Option<u64>,
+
+    /// Leave the first N lines of CSV files out of separator detection,
+    /// for files that start with comments or other metadata before the
+    /// data. [default: 0]
+    #[arg(long, value_name = "N")]
+    csv_skip_lines: Option<usize>,
+
+    /// Gzip each copy at LEVEL, 0 to 9 or `fast`, `default` and `best`.
+    /// Archives, packages and other formats that are compressed already
+    /// are stored as is, as is everything at level 0.
+    #[arg(long, value_name = "LEVEL", value_parser = parse_compress_level)]
+    compress_level: Option<u32>,
+
+    /// Stop gracefully once the run has taken this long, e.g. `30m` or
+    /// `1h30m`, keeping the manifest of what was copied so far.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Give up on a file whose contents take longer than this to read,
+    /// e.g. `30s`, as on a hung network share or a failing disk. It is
+    /// reported as timed out and the scan moves on.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    file_timeout: Option<Duration>,
+
+    /// Skip hidden files and directories: those with the hidden attribute
+    /// on Windows, dotfiles elsewhere.
+    #[arg(long)]
+    skip_hidden: bool,
+
+    /// Skip files and directories with the Windows system attribute.
+    #[arg(long)]
+    skip_system: bool,
+
+    /// Print extra detail, such as whether a reported entry is hidden.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Output format for reported entries [default: text].
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// In `--format tree`, show a directory holding nothing but another
+    /// directory on one line with it, as in `com/example/app/`. Only the
+    /// output changes, not what is copied.
+    #[arg(long)]
+    flatten_single_child_dirs: bool,
+
+    /// Write sizes in text output with binary units, like `1.5 GiB`,
+    /// instead of as byte counts.
+    #[arg(long)]
+    human: bool,
+
+    /// Like `--human`, with decimal units, like `1.6 GB`.
+    #[arg(long, conflicts_with = "human")]
+    si: bool,
+
+    /// Read the files of each directory back to back, in name order, before
+    /// descending into its subdirectories. This helps on spinning disks,
+    /// where seeking between directories costs far more than the read
+    /// itself; on SSDs the default directory order is just as fast.
+    #[arg(long)]
+    sequential: bool,
+
+    /// Digest recorded for each copied file in the destination's manifest
+    /// [default: sha256].
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+
+    /// Instead of scanning, re-hash the copies in `--dest` and compare them
+    /// against its manifest, using the algorithm it was written with.
+    #[arg(long)]
+    verify: bool,
+
+    /// After the backup, re-read every copy in `--dest` as `--verify` does,
+    /// failing the run if any of them doesn't match what was read from the
+    /// source.
+    #[arg(long, conflicts_with_all = ["verify", "diff_dest"])]
+    verify_after: bool,
+
+    /// Read the entry headers of zip archives to report whether they are
+    /// encrypted and how many entries they hold at the top level. Nothing
+    /// is extracted.
+    #[arg(long)]
+    inspect_archives: bool,
+
+    /// Decompress zips and gzip files to check them against their CRC32s,
+    /// flagging corrupt ones before they are backed up. Slow, as every byte
+    /// inside is read.
+    #[arg(long)]
+    check_crc: bool,
+
+    /// Check JPEG photos and MP4, MOV, MKV and WebM videos for a truncated
+    /// or broken container, reporting damaged ones as `media(...)` and
+    /// flagging them corrupt. Only headers and the end of each file are
+    /// read, so damage inside the picture itself goes unnoticed.
+    #[arg(long)]
+    check_media: bool,
+
+    /// Parse certificate files to report their subject and expiry date,
+    /// flagging expired ones and those expiring within 30 days. Bundles
+    /// also report how many certificates they hold and whether they are
+    /// ordered leaf first, each issued by the next.
+    #[arg(long)]
+    inspect_certs: bool,
+
+    /// Read the EXIF metadata of photos and report those that record where
+    /// they were taken, with their GPS coordinates and capture date, so
+    /// they can be found before a backup is shared.
+    #[arg(long)]
+    inspect_exif: bool,
+
+    /// Report what disk images hold, classifying their files as if they
+    /// were another root. `.iso` files are read in place; `.vhd` and
+    /// `.vhdx` ones are mounted read-only on Windows and dismounted once
+    /// walked. Images inside images are not looked into.
+    #[arg(long)]
+    mount_images: bool,
+
+    /// Classify the files inside zip, tar and gzipped archives, reported as
+    /// `archive.zip!/inner/path`. Archives inside archives are looked into
+    /// up to three levels deep, and at most 1 GiB is unpacked per archive
+    /// so that zip bombs can't fill the temporary folder.
+    #[arg(long)]
+    scan_archives: bool,
+
+    /// Store contents that were already backed up this run only once,
+    /// linking later copies to the first one, whichever drive either came
+    /// from. Drives scanned in parallel share one index of what was copied.
+    /// Falls back to copying where the destination can't hard link.
+    #[arg(long)]
+    dedupe_across_drives: bool,
+
+    /// Also copy the NTFS alternate data streams of backed up files, each
+    /// into a sidecar file named `<file>.<stream>.ads` next to the copy.
+    #[arg(long)]
+    backup_streams: bool,
+
+    /// Walk each root depth-first or breadth-first [default: dfs].
+    #[arg(long, value_enum)]
+    traversal: Option<Traversal>,
+
+    /// Leave out the files of directories that directly hold more than N
+    /// files, such as `WinSxS` or other sprawling system trees. Their
+    /// subdirectories are still scanned. This is coarse and can drop files
+    /// you want, so check the warnings it prints.
+    #[arg(long, value_name = "N")]
+    skip_dirs_over: Option<usize>,
+
+    /// Whether to skip directories over `--skip-dirs-over` outright or ask
+    /// about each [default: skip].
+    #[arg(long, value_enum)]
+    large_dirs: Option<LargeDirs>,
+
+    /// Store manifest sources relative to ROOT so the backup can be restored
+    /// on another machine. Sources outside ROOT stay absolute. With
+    /// `--restore`, relative sources are put back below this ROOT instead
+    /// of the one recorded.
+    #[arg(long, value_name = "ROOT")]
+    relative_to: Option<PathBuf>,
+
+    /// Instead of scanning, copy the files in `--dest` back to their
+    /// sources as recorded in its manifest, never overwriting existing
+    /// files.
+    #[arg(long, conflicts_with = "verify")]
+    restore: bool,
+
+    /// Instead of scanning, compare two manifests written by this tool,
+    /// each a `manifest.json` or a backup folder holding one, listing the
+    /// files added, removed and modified from OLD to NEW with counts and
+    /// size changes. Neither the sources nor the copies are read.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["OLD", "NEW"],
+        conflicts_with_all = ["verify", "restore"]
+    )]
+    compare_manifests: Option<Vec<PathBuf>>,
+
+    /// Instead of copying, compare every file that would be backed up with
+    /// the copy already at its place in `--dest`, listing the ones that
+    /// would be overwritten and counting them along with the identical and
+    /// new ones. Nothing is written.
+    #[arg(long, value_enum, value_name = "BY", conflicts_with_all = ["verify", "restore"])]
+    diff_dest: Option<DiffBy>,
+
+    /// Record the Unix mode, owner and group of every copied file in the
+    /// manifest and apply them when restoring. Owners are only restored
+    /// when running as root. Has no effect on Windows.
+    #[arg(long)]
+    preserve_permissions: bool,
+
+    /// Take a volume shadow copy of every scanned volume before the walk
+    /// and copy files from it, so locked ones like `NTUSER.DAT` and open
+    /// databases are backed up too, all as of the same moment. Files are
+    /// still found and classified on the live volume. The shadow copies
+    /// are deleted when the run ends. Windows only, and needs an
+    /// administrator.
+    #[arg(long)]
+    use_vss: bool,
+
+    /// Hash the matching files and report how many have identical
+    /// contents and how much space that wastes, without listing or copying
+    /// anything.
+    #[arg(long)]
+    dedup_report: bool,
+
+    /// Classify on N threads; output keeps the order of a serial run.
+    /// Defaults to the number of CPUs, `--sequential` forces 1.
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Scan at most N drives at once. Roots on the same physical disk are
+    /// always scanned one after the other, so this mostly matters for
+    /// several disks behind one USB hub or controller. Output of drives
+    /// scanned together is interleaved. Defaults to the number of disks,
+    /// or of CPUs if some disk can't be determined. `--sequential` forces 1.
+    #[arg(long, value_name = "N")]
+    max_parallel_drives: Option<NonZeroUsize>,
+
+    /// Read the next N files of the walk in the background while the
+    /// current one is handled, so they are in the OS cache by the time they
+    /// are classified and copied. Helps on network shares and spinning
+    /// disks, at the cost of more disk and cache use. Up to 16 MiB of each
+    /// file is read ahead.
+    #[arg(long, value_name = "N")]
+    readahead: Option<NonZeroUsize>,
+
+    /// Don't list entries, only print file counts and sizes per category
+    /// once the scan is done. Copying still happens as usual.
+    #[arg(long)]
+    summary_only: bool,
+
+    /// Break the totals of `--summary-only` and `--stats-json` down per
+    /// root too, to see which drive holds what, followed by those of all of
+    /// them together.
+    #[arg(long)]
+    classification_stats_per_drive: bool,
+
+    /// List the files that weren't classified as anything too, tagged
+    /// `regular`, to tell a file that was seen apart from one the walk
+    /// never got to. They are still not backed up.
+    #[arg(long)]
+    show_regular: bool,
+
+    /// Print file counts and sizes per extension once the scan is done,
+    /// for every file walked, not only the reported ones.
+    #[arg(long)]
+    by_extension: bool,
+
+    /// Once the scan is done, print per category how many reported files
+    /// have a signature confirming their classification, one belonging to
+    /// another category, or none known at all, so only their name was to
+    /// go by. Surfaces misclassification, like `.db` files that aren't
+    /// databases.
+    #[arg(long, conflicts_with = "classify_only")]
+    magic_report: bool,
+
+    /// Sort the `--by-extension` report by file count or total size
+    /// [default: count].
+    #[arg(long, value_enum)]
+    sort_extensions: Option<ExtensionOrder>,
+
+    /// Print the directories holding the most bytes of reported files once
+    /// the scan is done, each counting everything below it, like `du` for
+    /// what the scan found.
+    #[arg(long)]
+    dir_sizes: bool,
+
+    /// How many directories `--dir-sizes` lists, largest first
+    /// [default: 20].
+    #[arg(long, value_name = "N")]
+    dir_sizes_top: Option<usize>,
+
+    /// Write the statistics of the run to FILE as JSON once it ends, even
+    /// a cancelled one: counts per category and extension, throughput,
+    /// and how many files were copied, skipped or locked. Meant for
+    /// monitoring backups over time.
+    #[arg(long, value_name = "FILE")]
+    stats_json: Option<PathBuf>,
+
+    /// Classify by file name and extension alone, never opening a file to
+    /// look inside. Much faster for a full-drive inventory, but CSV
+    /// separators and plist formats are reported as unknown and checks
+    /// like macro detection are skipped. Disables the cache.
+    #[arg(long, visible_alias = "fast")]
+    classify_only: bool,
+
+    /// Format text output lines with placeholders: {path}, {class},
+    /// {marker}, {tags}, {category}, {size} and {mtime}, e.g.
+    /// `{size}\t{path}`.
+    #[arg(long)]
+    template: Option<String>,
+
+    /// Print the JSON Schema of the `--format json` records and exit.
+    #[arg(long)]
+    pub json_schema: bool,
+
+    /// Print every category and type tag files can be reported with, along
+    /// with what each matches, and exit.
+    #[arg(long)]
+    pub list_categories: bool,
+}
+
+impl Args {
+    /// Builds the scan configuration from these arguments layered over the
+    /// settings file.
+    pub fn into_config(self) -> anyhow::Result<ScanOptions> {
+        let file = load_config(self.config.as_deref())?;
+
+        if let Some(path) = self.rules.as_ref().or(file.rules.as_ref()) {
+            install_rules(load_rules(path)?);
+        }
+
+        let mut category_dirs = file
+            .category_dirs
+            .into_iter()
+            .map(|(category, dir)| (category.to_ascii_lowercase(), dir))
+            .collect::<HashMap<_, _>>();
+        category_dirs.extend(self.category_dirs);
+
+        // an explicit cache path on the command line beats `no-cache` in the
+        // settings file
+        let classify_only = self.classify_only || file.classify_only;
+        // the cache only holds content-based results, which wouldn't be
+        // produced here, and must not be filled with the unknown ones
+        let no_cache = classify_only || self.no_cache || (file.no_cache && self.cache.is_none());
+        let count_first = self.count_first || file.count_first;
+        let sequential = self.sequential || file.sequential;
+        // either flag on the command line overrides the file's choice
+        let sizes = match (self.human, self.si, file.human, file.si) {
+            (true, _, _, _) => SizeFormat::Binary,
+            (_, true, _, _) => SizeFormat::Si,
+            (_, _, true, _) => SizeFormat::Binary,
+            (_, _, _, true) => SizeFormat::Si,
+            _ => SizeFormat::Bytes,
+        };
+
+        let mut config = ScanOptions {
+            // absolute like the destination, so the walk can tell when it
+            // reaches it
+            roots: if self.roots.is_empty() {
+                file.roots
+            } else {
+                self.roots
+            }
+            .into_iter()
+            .map(path::absolute)
+            .collect::<Result<_, _>>()
+            .context("failed to resolve the roots")?,
+            dest: self
+                .dest
+                .or(file.dest)
+                .map(path::absolute)
+                .transpose()
+                .context("failed to resolve the destination")?,
+            layout: self.layout.or(file.layout).unwrap_or_default(),
+            manifest_format: self
+                .manifest_format
+                .or(file.manifest_format)
+                .unwrap_or_default(),
+            on_illegal_name: self
+                .on_illegal_name
+                .or(file.on_illegal_name)
+                .unwrap_or_default(),
+            on_dest_error: self
+                .on_dest_error
+                .or(file.on_dest_error)
+                .unwrap_or_default(),
+            windows_names: cfg!(windows),
+            category_dirs,
+            one_file_system: self.one_file_system || file.one_file_system,
+            drive_label: self.drive_label || file.drive_label,
+            include_temp: self.include_temp || file.include_temp,
+            include_recycle_bin: self.include_recycle_bin || file.include_recycle_bin,
+            skip_bak: self.skip_bak.or(file.skip_bak).unwrap_or(true),
+            follow_links: self.follow_links.or(file.follow_links).unwrap_or(true),
+            max_symlink_depth: self.max_symlink_depth.or(file.max_symlink_depth),
+            follow_junctions: self
+                .follow_junctions
+                .or(file.follow_junctions)
+                .unwrap_or(true),
+            follow_reparse: match self.follow_reparse {
+                Some(tags) => Some(tags),
+                None => file
+                    .follow_reparse
+                    .as_deref()
+                    .map(|tags| tags.iter().map(|tag| parse_reparse_tag(tag)).collect())
+                    .transpose()?,
+            },
+            cache: if no_cache {
+                None
+            } else {
+                Some(
+                    self.cache
+                        .or(file.cache)
+                        .unwrap_or_else(|| state_dir().join("classify-cache.json")),
+                )
+            },
+            resume_from: self
+                .resume_from
+                .map(path::absolute)
+                .transpose()
+                .context("failed to resolve the resume cursor")?,
+            newer_than: self
+                .newer_than
+                .or(file.newer_than)
+                .map(|reference| {
+                    fs::metadata(&reference)
+                        .and_then(|metadata| metadata.modified())
+                        .with_context(|| {
+                            format!("failed to read mtime of `{}`", reference.display())
+                        })
+                })
+                .transpose()?,
+            empty_files: self.empty_files.or(file.empty_files).unwrap_or_default(),
+            progress: self.progress || file.progress || count_first,
+            count_first,
+            skip_vcs_internals: self.skip_vcs_internals || file.skip_vcs_internals,
+            min_free: match self.min_free {
+                Some(size) => Some(size),
+                None => file.min_free.as_deref().map(parse_size).transpose()?,
+            },
+            csv_sample_bytes: match self.csv_sample_bytes {
+                Some(size) => size,
+                None => file
+                    .csv_sample_bytes
+                    .as_deref()
+                    .map(parse_size)
+                    .transpose()?
+                    .unwrap_or(DEFAULT_CSV_SAMPLE_BYTES),
+            },
+            csv_skip_lines: self.csv_skip_lines.or(file.csv_skip_lines).unwrap_or(0),
+            progress_to: self.progress_to.or(file.progress_to),
+            progress_interval: match self.progress_interval {
+                Some(interval) => interval,
+                None => file
+                    .progress_interval
+                    .as_deref()
+                    .map(parse_duration)
+                    .transpose()?
+                    .unwrap_or(DEFAULT_PROGRESS_INTERVAL),
+            },
+            compress_level: match self.compress_level {
+                Some(level) => Some(level),
+                None => file
+                    .compress_level
+                    .as_deref()
+                    .map(parse_compress_level)
+                    .transpose()?,
+            },
+            skip_hidden: self.skip_hidden || file.skip_hidden,
+            skip_system: self.skip_system || file.skip_system,
+            verbose: self.verbose || file.verbose,
+            format: self.format.or(file.format).unwrap_or_default(),
+            flatten_single_child_dirs: self.flatten_single_child_dirs
+                || file.flatten_single_child_dirs,
+            sizes,
+            sequential,
+            hash_algo: self.hash_algo.or(file.hash_algo).unwrap_or_default(),
+            verify: self.verify,
+            verify_after: self.verify_after || file.verify_after,
+            dedup_report: self.dedup_report,
+            relative_to: self
+                .relative_to
+                .or(file.relative_to)
+                .map(path::absolute)
+                .transpose()
+                .context("failed to resolve `--relative-to`")?,
+            restore: self.restore,
+            compare_manifests: self.compare_manifests.and_then(|paths| {
+                let [old, new] = <[PathBuf; 2]>::try_from(paths).ok()?;
+                Some((old, new))
+            }),
+            diff_dest: self.diff_dest,
+            preserve_permissions: self.preserve_permissions || file.preserve_permissions,
+            use_vss: self.use_vss || file.use_vss,
+            summary_only: self.summary_only || file.summary_only,
+            stats_per_drive: self.classification_stats_per_drive
+                || file.classification_stats_per_drive,
+            show_regular: self.show_regular || file.show_regular,
+            by_extension: (self.by_extension || file.by_extension).then(|| {
+                self.sort_extensions
+                    .or(file.sort_extensions)
+                    .unwrap_or_default()
+            }),
+            dir_sizes: (self.dir_sizes || file.dir_sizes).then(|| {
+                self.dir_sizes_top
+                    .or(file.dir_sizes_top)
+                    .unwrap_or(DEFAULT_DIR_SIZES_TOP)
+            }),
+            magic_report: self.magic_report || file.magic_report,
+            stats_json: self.stats_json.or(file.stats_json),
+            classify_only,
+            template: self
+                .template
+                .or(file.template)
+                .as_deref()
+                .map(Template::parse)
+                .transpose()?,
+            // one reader keeps a spinning disk from seeking between files
+            jobs: if sequential {
+                1
+            } else {
+                self.jobs
+                    .or(file.jobs)
+                    .or_else(|| thread::available_parallelism().ok())
+                    .map_or(1, NonZeroUsize::get)
+            },
+            max_parallel_drives: if sequential {
+                Some(1)
+            } else {
+                self.max_parallel_drives
+                    .or(file.max_parallel_drives)
+                    .map(NonZeroUsize::get)
+            },
+            readahead: self.readahead.or(file.readahead).map(NonZeroUsize::get),
+            inspect_archives: self.inspect_archives || file.inspect_archives,
+            check_crc: self.check_crc || file.check_crc,
+            check_media: self.check_media || file.check_media,
+            inspect_certs: self.inspect_certs || file.inspect_certs,
+            inspect_exif: self.inspect_exif || file.inspect_exif,
+            mount_images: self.mount_images || file.mount_images,
+            scan_archives: self.scan_archives || file.scan_archives,
+            dedupe_across_drives: self.dedupe_across_drives || file.dedupe_across_drives,
+            backup_streams: self.backup_streams || file.backup_streams,
+            traversal: self.traversal.or(file.traversal).unwrap_or_default(),
+            skip_dirs_over: self.skip_dirs_over.or(file.skip_dirs_over),
+            large_dirs: self.large_dirs.or(file.large_dirs).unwrap_or_default(),
+            deadline: match self.max_runtime {
+                Some(budget) => Some(budget),
+                None => file
+                    .max_runtime
+                    .as_deref()
+                    .map(parse_duration)
+                    .transpose()?,
+            }
+            .map(|budget| Instant::now() + budget),
+            file_timeout: match self.file_timeout {
+                Some(timeout) => Some(timeout),
+                None => file
+                    .file_timeout
+                    .as_deref()
+                    .map(parse_duration)
+                    .transpose()?,
+            },
+        };
+        // the destination may be a FAT or NTFS drive mounted elsewhere
+        config.windows_names |= config.dest.as_deref().is_some_and(has_windows_names);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_key_value(arg: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = arg
+        .split_once('=')
+        .with_context(|| format!("expected `KEY=VALUE`, got `{}`", arg))?;
+    Ok((key.trim().to_ascii_lowercase(), value.trim().to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, set_mtime, TempTree};
+    use std::time::SystemTime;
+
+    /// The configuration for a command line, reading settings only from
+    /// an empty file in `tree` rather than the user's own.
+    fn config_for(tree: &TempTree, args: &[&str]) -> ScanOptions {
+        config_with(tree, "", args)
+    }
+
+    /// The configuration for a command line with `settings` as the
+    /// settings file.
+    fn config_with(tree: &TempTree, settings: &str, args: &[&str]) -> ScanOptions {
+        let settings = tree.file("config.toml", settings);
+        let settings = settings.to_str().unwrap();
+        Args::try_parse_from(["backup", "--config", settings].iter().chain(args))
+            .unwrap()
+            .into_config()
+            .unwrap()
+    }
+
+    #[test]
+    fn relative_roots_are_resolved_like_the_destination() {
+        let tree = TempTree::new();
+        let config = config_for(&tree, &["some/root", "--dest", "some/root/out"]);
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(config.roots, [cwd.join("some/root")]);
+        assert_eq!(config.dest.as_deref(), Some(&*cwd.join("some/root/out")));
+        assert!(config.is_destination(&config.roots[0].join("out")));
+    }
+
+    #[test]
+    fn newer_than_keeps_only_files_modified_after_the_reference() {
+        let tree = TempTree::new();
+        let now = SystemTime::now();
+        let marker = tree.file("marker", "");
+        set_mtime(&marker, now - Duration::from_secs(3600));
+        let old = tree.file("old.env", "");
+        set_mtime(&old, now - Duration::from_secs(7200));
+        let new = tree.file("new.env", "");
+        set_mtime(&new, now);
+
+        let config = config_for(&tree, &["--newer-than", marker.to_str().unwrap()]);
+        assert!(config.matches_filters(&entry(&new)));
+        assert!(!config.matches_filters(&entry(&old)));
+        // directories are still walked into
+        assert!(config.matches_filters(&entry(&tree.dir("sub"))));
+    }
+
+    #[test]
+    fn command_line_flags_win_over_the_settings_file() {
+        let tree = TempTree::new();
+        let settings = "roots = [\"from-file\"]\ndest = \"backup\"\nlayout = \"by-category\"\n\
+                        jobs = 3\none-file-system = true\n\n[category-dirs]\nsecrets = \"keys\"\n";
+
+        let config = config_with(&tree, settings, &[]);
+        assert_eq!(config.roots, [tree.path().join("from-file")]);
+        assert_eq!(config.dest.as_deref(), Some(&*tree.path().join("backup")));
+        assert_eq!(config.layout, Layout::ByCategory);
+        assert_eq!(config.jobs, 3);
+        assert!(config.one_file_system);
+
+        let cwd = std::env::current_dir().unwrap();
+        let config = config_with(
+            &tree,
+            settings,
+            &[
+                "cli-root", "--dest", "out", "--layout", "mirror", "--jobs", "5",
+            ],
+        );
+        assert_eq!(config.roots, [cwd.join("cli-root")]);
+        assert_eq!(config.dest.as_deref(), Some(&*cwd.join("out")));
+        assert_eq!(config.layout, Layout::Mirror);
+        assert_eq!(config.jobs, 5);
+        // switches can only be turned on from either side
+        assert!(config.one_file_system);
+    }
+
+    #[test]
+    fn category_dirs_from_the_command_line_extend_the_file() {
+        let tree = TempTree::new();
+        let settings = "[category-dirs]\nsecrets = \"keys\"\nmedia = \"photos\"\n";
+        let config = config_with(&tree, settings, &["--category-dir", "media=pictures"]);
+        assert_eq!(config.category_dirs["secrets"], "keys");
+        assert_eq!(config.category_dirs["media"], "pictures");
+    }
+
+    #[test]
+    fn sequential_mode_reads_with_a_single_thread() {
+        let tree = TempTree::new();
+        let config = config_for(&tree, &["--sequential", "--jobs", "8"]);
+        assert!(config.sequential);
+        assert_eq!(config.jobs, 1);
+        assert_eq!(config.max_parallel_drives, Some(1));
+    }
+
+    #[test]
+    fn size_flags_pick_the_units() {
+        let tree = TempTree::new();
+        assert_eq!(config_for(&tree, &[]).sizes, SizeFormat::Bytes);
+        assert_eq!(config_for(&tree, &["--human"]).sizes, SizeFormat::Binary);
+        assert_eq!(config_for(&tree, &["--si"]).sizes, SizeFormat::Si);
+        assert_eq!(config_with(&tree, "si = true", &[]).sizes, SizeFormat::Si);
+        assert_eq!(
+            config_with(&tree, "si = true", &["--human"]).sizes,
+            SizeFormat::Binary
+        );
+        assert!(Args::try_parse_from(["backup", "--human", "--si"]).is_err());
+    }
+}
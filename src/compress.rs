@@ -0,0 +1,157 @@
+use anyhow::{bail, Context};
+use flate2::{
+    read::{GzDecoder, MultiGzDecoder},
+    write::GzEncoder,
+    Compression,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use crate::{DataScienceFileType, EbookFormat, FileClassification, MobileKind, WebArchiveKind};
+
+/// Extension appended to copies that were gzipped.
+pub const COMPRESSED_EXTENSION: &str = "gz";
+
+/// Parses a gzip level for `--compress-level`: `0` to `9`, or `fast` (1),
+/// `default` (6) and `best` (9). Level 0 stores every file as is.
+pub fn parse_compress_level(text: &str) -> anyhow::Result<u32> {
+    let level = match text.trim().to_ascii_lowercase().as_str() {
+        "fast" => Compression::fast().level(),
+        "default" => Compression::default().level(),
+        "best" => Compression::best().level(),
+        number => number
+            .parse()
+            .with_context(|| format!("invalid compression level `{}`", text))?,
+    };
+    if level > 9 {
+        bail!("compression level must be between 0 and 9, got {}", level);
+    }
+
+    Ok(level)
+}
+
+/// Whether gzipping a file of this kind is likely to pay off. Formats that
+/// are compressed or encrypted already, like archives, packages, most
+/// ebooks and PGP messages, would only burn CPU to come out the same size
+/// or slightly larger.
+pub fn should_compress(class: &FileClassification) -> bool {
+    if let Some(class) = class.wrapped() {
+        return should_compress(class);
+    }
+    !matches!(
+        class,
+        FileClassification::Archive(_)
+            | FileClassification::Installer(_)
+            | FileClassification::Ebook(EbookFormat::Epub | EbookFormat::Mobi | EbookFormat::Djvu)
+            | FileClassification::DataScience(
+                DataScienceFileType::Parquet | DataScienceFileType::Numpy
+            )
+            | FileClassification::MobileBackup(MobileKind::AndroidBackup)
+            | FileClassification::Encrypted(_)
+            | FileClassification::GeotaggedPhoto
+            | FileClassification::CorruptMedia(_)
+            | FileClassification::WebArchive(
+                WebArchiveKind::Warc { compressed: true } | WebArchiveKind::Maff
+            )
+    )
+}
+
+/// Writes a gzipped copy of `source` to `target`, returning the number of
+/// bytes read from the source.
+pub fn compress_file(source: &Path, target: &Path, level: u32) -> io::Result<u64> {
+    let mut reader = File::open(source)?;
+    let mut encoder = GzEncoder::new(File::create(target)?, Compression::new(level));
+    let size = io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+
+    Ok(size)
+}
+
+/// Whether a gzip file decompresses cleanly, each member matching the CRC32
+/// and length in its trailer.
+pub fn gzip_is_intact(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| {
+        io::copy(
+            &mut MultiGzDecoder::new(BufReader::new(file)),
+            &mut io::sink(),
+        )
+        .is_ok()
+    })
+}
+
+/// Opens a copy in the destination for reading its original contents,
+/// decompressing it on the fly if it was stored gzipped.
+pub fn open_copy(path: &Path, compressed: bool) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(if compressed {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_class, TempTree};
+    use std::fs;
+
+    #[test]
+    fn levels_are_numbers_or_names() {
+        assert_eq!(parse_compress_level("0").unwrap(), 0);
+        assert_eq!(parse_compress_level(" 9 ").unwrap(), 9);
+        assert_eq!(parse_compress_level("fast").unwrap(), 1);
+        assert_eq!(parse_compress_level("Default").unwrap(), 6);
+        assert_eq!(parse_compress_level("best").unwrap(), 9);
+        assert!(parse_compress_level("10").is_err());
+        assert!(parse_compress_level("max").is_err());
+    }
+
+    #[test]
+    fn already_compressed_kinds_are_stored_as_is() {
+        let tree = TempTree::new();
+        for name in [
+            "photos.zip",
+            "setup.msi",
+            "book.epub",
+            "data.parquet",
+            "note.gpg",
+        ] {
+            let class = file_class(&tree.file(name, "x"));
+            assert!(!should_compress(&class), "{}", name);
+        }
+        for name in [".env", "book.fb2", "dump.sql", "id_rsa"] {
+            let class = file_class(&tree.file(name, "x"));
+            assert!(should_compress(&class), "{}", name);
+        }
+    }
+
+    #[test]
+    fn compressed_copies_read_back_as_the_original() {
+        let tree = TempTree::new();
+        let contents = "KEY=value\n".repeat(1000);
+        let source = tree.file("source", &contents);
+        let target = tree.path().join("copy.gz");
+
+        assert_eq!(
+            compress_file(&source, &target, 9).unwrap(),
+            contents.len() as u64
+        );
+        assert!(fs::metadata(&target).unwrap().len() < contents.len() as u64 / 10);
+        assert!(gzip_is_intact(&target));
+        let mut read = String::new();
+        open_copy(&target, true)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, contents);
+
+        let mut truncated = fs::read(&target).unwrap();
+        truncated.truncate(truncated.len() - 4);
+        fs::write(&target, truncated).unwrap();
+        assert!(!gzip_is_intact(&target));
+    }
+}
@@ -0,0 +1,1292 @@
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use walkdir::DirEntry;
+
+use crate::{
+    hash::HashAlgo,
+    platform::{config_dir, entry_reparse_tag},
+    rules::custom_labels,
+    template::Template,
+    CATEGORIES,
+};
+
+/// How copied files are arranged below the destination root.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Recreate the source tree, with the drive as the first component.
+    #[default]
+    Mirror,
+    /// Group files by category first, then recreate the source tree below it.
+    ByCategory,
+}
+
+/// What to do with zero-byte files, which are often placeholders or
+/// accidentally truncated documents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyFiles {
+    /// Treat them like any other file.
+    #[default]
+    Backup,
+    /// List them tagged as empty, but don't copy them.
+    Report,
+    /// Leave them out entirely.
+    Skip,
+}
+
+/// What happens to a file whose name can't be used on the destination, as
+/// it has characters the destination doesn't allow or is too long.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IllegalNames {
+    /// Leave the file out with a warning.
+    Skip,
+    /// Copy it under a name with the offending characters replaced and
+    /// overlong parts shortened. The manifest keeps the original path.
+    #[default]
+    Sanitize,
+    /// Stop the run.
+    Error,
+}
+
+/// What happens when writing a file's copy to the destination fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DestErrors {
+    /// Leave the file out with a warning, listing it in the manifest.
+    Skip,
+    /// Stop the run.
+    #[default]
+    Abort,
+    /// Try the copy again a few times, waiting longer each time, then stop
+    /// the run.
+    Retry,
+}
+
+/// What happens to a directory holding more files than `--skip-dirs-over`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LargeDirs {
+    /// Leave its files out and say so on stderr.
+    #[default]
+    Skip,
+    /// Ask on the terminal whether to leave its files out. Without a
+    /// terminal to ask on, they are left out.
+    Ask,
+}
+
+/// What `--diff-dest` compares an existing copy with its source by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffBy {
+    /// Same size means identical.
+    #[default]
+    Size,
+    /// Same size and a copy no older than its source means identical.
+    Mtime,
+    /// Same contents, hashed with `--hash-algo`, means identical.
+    Hash,
+}
+
+/// How the `--by-extension` report is sorted, largest first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExtensionOrder {
+    /// By the number of files.
+    #[default]
+    Count,
+    /// By their total size.
+    Size,
+}
+
+/// Order in which each root's tree is visited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Traversal {
+    /// Finish each subdirectory before moving on to its siblings.
+    #[default]
+    Dfs,
+    /// Visit all entries at one depth before going any deeper, so shallow
+    /// files are secured first.
+    Bfs,
+}
+
+/// How reported entries are written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// One `path # class` line per entry.
+    #[default]
+    Text,
+    /// One JSON object per line, see `--json-schema`.
+    Json,
+    /// Entries grouped below their directories as an indented tree,
+    /// printed once each root is scanned.
+    Tree,
+}
+
+/// Extra form the manifest is written in, next to the JSON manifest that
+/// `--verify`, `--restore` and `--diff-dest` read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestFormat {
+    /// Only `manifest.json`.
+    #[default]
+    Json,
+    /// Also `manifest.csv`, one row per copy, for spreadsheets.
+    Csv,
+    /// Also `manifest.sqlite`, with indexed `entries` for queries over
+    /// large backups.
+    Sqlite,
+}
+
+/// How sizes are written in text output. JSON always carries raw byte
+/// counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeFormat {
+    /// Plain byte counts.
+    #[default]
+    Bytes,
+    /// Binary units, `1.5 GiB`.
+    Binary,
+    /// Decimal units, `1.5 GB`.
+    Si,
+}
+
+impl SizeFormat {
+    /// `bytes` as a bare number, or with its unit when human-readable.
+    pub fn amount(self, bytes: u64) -> String {
+        match self {
+            Self::Bytes => bytes.to_string(),
+            Self::Binary => format_size(bytes, true),
+            Self::Si => format_size(bytes, false),
+        }
+    }
+
+    /// Like [`Self::amount`], but bare numbers are followed by `bytes`.
+    pub fn describe(self, bytes: u64) -> String {
+        match self {
+            Self::Bytes => format!("{} bytes", bytes),
+            _ => self.amount(bytes),
+        }
+    }
+}
+
+/// Formats a size with one decimal in the largest unit it reaches, e.g.
+/// `340.0 KiB`, base 1024 if `binary` and 1000 otherwise. Sizes below the
+/// first unit stay whole, like `1023 B`.
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64, binary: bool) -> String {
+    let (base, units) = if binary {
+        (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+    } else {
+        (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"])
+    };
+
+    let mut value = bytes as f64;
+    if value < base {
+        return format!("{} B", bytes);
+    }
+    let mut unit = 0;
+    value /= base;
+    // bump before rounding would print e.g. `1024.0 KiB`
+    while unit + 1 < units.len() && value >= base - 0.05 {
+        value /= base;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, units[unit])
+}
+
+#[derive(Debug)]
+pub struct ScanOptions {
+    /// Directories to scan, all drives if empty.
+    pub roots: Vec<PathBuf>,
+    pub dest: Option<PathBuf>,
+    pub layout: Layout,
+    pub manifest_format: ManifestFormat,
+    /// Custom subfolder for a category in the `by-category` layout, keyed by
+    /// category name. Unmapped categories use their name as the folder.
+    pub category_dirs: HashMap<String, String>,
+    pub on_illegal_name: IllegalNames,
+    pub on_dest_error: DestErrors,
+    /// Whether copies must have names Windows allows: the destination is
+    /// on Windows or on a FAT, exFAT or NTFS volume.
+    pub windows_names: bool,
+    /// Don't descend into directories on a different filesystem than the
+    /// scanned root.
+    pub one_file_system: bool,
+    /// Report the volume label and filesystem of each root.
+    pub drive_label: bool,
+    /// Keep temporary and editor files instead of pruning them.
+    pub include_temp: bool,
+    /// Descend into recycle bins instead of pruning them.
+    pub include_recycle_bin: bool,
+    /// Prune `.bak`, `.old` and `file~` copies too, unless `include_temp`
+    /// is set.
+    pub skip_bak: bool,
+    pub follow_links: bool,
+    /// Longest chain of links to follow, `None` for whatever the system
+    /// allows.
+    pub max_symlink_depth: Option<usize>,
+    /// Follow NTFS junctions too. Only has an effect while following links,
+    /// as junctions are reported as symlinks.
+    pub follow_junctions: bool,
+    /// Reparse tags to walk into, with reparse points of any other kind
+    /// left out. `None` leaves them all to the link settings.
+    pub follow_reparse: Option<Vec<u32>>,
+    /// Where classifications that required reading file contents are cached
+    /// between runs, `None` to disable caching.
+    pub cache: Option<PathBuf>,
+    /// Only consider files modified after this point in time.
+    pub newer_than: Option<SystemTime>,
+    /// Leave out everything that sorts before this path, see
+    /// [`Self::is_before_cursor`].
+    pub resume_from: Option<PathBuf>,
+    pub empty_files: EmptyFiles,
+    /// Show progress on stderr while scanning.
+    pub progress: bool,
+    /// Count the files to scan up front so progress can show a total and
+    /// an ETA.
+    pub count_first: bool,
+    /// File progress is appended to every `progress_interval`.
+    pub progress_to: Option<PathBuf>,
+    pub progress_interval: Duration,
+    /// Report `.git`/`.svn` directories without descending into them, and
+    /// don't copy the `.git` files of submodules and worktrees.
+    pub skip_vcs_internals: bool,
+    /// Refuse to copy a file if it would leave less than this many bytes
+    /// free on the destination.
+    pub min_free: Option<u64>,
+    /// Gzip level for copies that are worth compressing.
+    pub compress_level: Option<u32>,
+    /// How much of a CSV file is read to detect its separator.
+    pub csv_sample_bytes: u64,
+    /// Lines at the start of a CSV file left out of separator detection.
+    pub csv_skip_lines: usize,
+    /// Prune hidden files and directories.
+    pub skip_hidden: bool,
+    /// Prune files and directories with the system attribute.
+    pub skip_system: bool,
+    /// Print extra detail about reported entries.
+    pub verbose: bool,
+    pub format: OutputFormat,
+    /// In tree output, show directories holding nothing but one directory
+    /// on the same line as it.
+    pub flatten_single_child_dirs: bool,
+    pub sizes: SizeFormat,
+    /// Visit files in an order meant to keep a spinning disk's head from
+    /// jumping around.
+    pub sequential: bool,
+    pub hash_algo: HashAlgo,
+    /// Check the destination against its manifest instead of scanning.
+    pub verify: bool,
+    /// Check the destination against its manifest once the backup is done.
+    pub verify_after: bool,
+    /// Look inside zips for encryption and their entry count.
+    pub inspect_archives: bool,
+    /// Check zips and gzip files against their CRC32s.
+    pub check_crc: bool,
+    /// Check photos and videos for a truncated or broken container.
+    pub check_media: bool,
+    /// Parse certificates for their subject and expiry.
+    pub inspect_certs: bool,
+    /// Read EXIF metadata of photos for GPS coordinates.
+    pub inspect_exif: bool,
+    /// List what disk images hold, mounting virtual disks on Windows.
+    pub mount_images: bool,
+    /// Classify the files inside zip and tar archives.
+    pub scan_archives: bool,
+    /// Link copies of contents already backed up this run instead of
+    /// copying them again.
+    pub dedupe_across_drives: bool,
+    /// Copy alternate data streams into sidecar files.
+    pub backup_streams: bool,
+    pub traversal: Traversal,
+    /// Directories with more files directly inside are handled according
+    /// to `large_dirs`.
+    pub skip_dirs_over: Option<usize>,
+    pub large_dirs: LargeDirs,
+    /// When to stop gracefully, from `--max-runtime`.
+    pub deadline: Option<Instant>,
+    /// How long reading a single file may take before it is given up on.
+    pub file_timeout: Option<Duration>,
+    /// Only report duplicate files instead of listing and copying.
+    pub dedup_report: bool,
+    /// Root that manifest sources are stored relative to, and that
+    /// `--restore` puts them back under.
+    pub relative_to: Option<PathBuf>,
+    /// Copy the backup in the destination back to where it came from.
+    pub restore: bool,
+    /// Report what changed from the first manifest to the second instead
+    /// of scanning.
+    pub compare_manifests: Option<(PathBuf, PathBuf)>,
+    /// Compare sources against what the destination already holds instead
+    /// of copying them.
+    pub diff_dest: Option<DiffBy>,
+    /// Keep Unix modes and owners in the manifest and restore them.
+    pub preserve_permissions: bool,
+    /// Copy files from volume shadow copies taken at the start of the run.
+    pub use_vss: bool,
+    /// Threads classifying entries, 1 to do everything on the walker.
+    pub jobs: usize,
+    /// Drives scanned at the same time, see `--max-parallel-drives`.
+    /// `None` picks a limit from the disks being scanned.
+    pub max_parallel_drives: Option<usize>,
+    /// Files read ahead of the walk, see `--readahead`.
+    pub readahead: Option<usize>,
+    /// Print only the per-category totals at the end instead of every entry.
+    pub summary_only: bool,
+    /// Keep the per-category totals of each root apart as well.
+    pub stats_per_drive: bool,
+    /// List files classified as regular as well, tagged `regular`.
+    pub show_regular: bool,
+    /// Tally files per extension and print them at the end, in this order.
+    pub by_extension: Option<ExtensionOrder>,
+    /// Total the reported files per directory and print this many of the
+    /// largest at the end.
+    pub dir_sizes: Option<usize>,
+    /// Check reported files against their signatures and print how often
+    /// each category agreed.
+    pub magic_report: bool,
+    /// Where to write the statistics of the run as JSON once it ends.
+    pub stats_json: Option<PathBuf>,
+    /// Classify by name alone, with every check that would open the file
+    /// skipped, including `inspect_exif` and `check_media`.
+    pub classify_only: bool,
+    /// Custom format for text output lines.
+    pub template: Option<Template>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            dest: None,
+            layout: Layout::default(),
+            manifest_format: ManifestFormat::default(),
+            on_illegal_name: IllegalNames::default(),
+            on_dest_error: DestErrors::default(),
+            windows_names: cfg!(windows),
+            category_dirs: HashMap::new(),
+            one_file_system: false,
+            drive_label: false,
+            include_temp: false,
+            include_recycle_bin: false,
+            skip_bak: true,
+            follow_links: true,
+            max_symlink_depth: None,
+            follow_junctions: true,
+            follow_reparse: None,
+            cache: None,
+            newer_than: None,
+            resume_from: None,
+            empty_files: EmptyFiles::default(),
+            progress: false,
+            count_first: false,
+            skip_vcs_internals: false,
+            min_free: None,
+            compress_level: None,
+            csv_sample_bytes: DEFAULT_CSV_SAMPLE_BYTES,
+            progress_to: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            csv_skip_lines: 0,
+            skip_hidden: false,
+            skip_system: false,
+            verbose: false,
+            format: OutputFormat::Text,
+            flatten_single_child_dirs: false,
+            sizes: SizeFormat::Bytes,
+            sequential: false,
+            hash_algo: HashAlgo::Sha256,
+            verify: false,
+            verify_after: false,
+            inspect_archives: false,
+            check_crc: false,
+            check_media: false,
+            inspect_certs: false,
+            inspect_exif: false,
+            mount_images: false,
+            scan_archives: false,
+            dedupe_across_drives: false,
+            backup_streams: false,
+            traversal: Traversal::Dfs,
+            skip_dirs_over: None,
+            large_dirs: LargeDirs::Skip,
+            deadline: None,
+            file_timeout: None,
+            dedup_report: false,
+            relative_to: None,
+            restore: false,
+            compare_manifests: None,
+            diff_dest: None,
+            preserve_permissions: false,
+            use_vss: false,
+            jobs: 1,
+            max_parallel_drives: None,
+            readahead: None,
+            summary_only: false,
+            stats_per_drive: false,
+            show_regular: false,
+            by_extension: None,
+            dir_sizes: None,
+            magic_report: false,
+            stats_json: None,
+            classify_only: false,
+            template: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Starts from the defaults, for setting the options a library caller
+    /// cares about one by one.
+    pub fn builder() -> ScanOptionsBuilder {
+        ScanOptionsBuilder::default()
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (category, dir) in &self.category_dirs {
+            let known = CATEGORIES
+                .iter()
+                .copied()
+                .chain(custom_labels())
+                .collect::<Vec<_>>();
+            if !known.contains(&category.as_str()) {
+                bail!(
+                    "unknown category `{}`, expected one of: {}",
+                    category,
+                    known.join(", ")
+                );
+            }
+            ensure_contained(Path::new(dir))
+                .with_context(|| format!("invalid folder for category `{}`", category))?;
+        }
+        if self.csv_sample_bytes == 0 {
+            bail!("the CSV sample must be at least one byte");
+        }
+        if self.file_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            bail!("the file timeout must be longer than zero");
+        }
+        if self.progress_interval.is_zero() {
+            bail!("the progress interval must be longer than zero");
+        }
+        // a collision would link a file to the copy of a different one
+        if self.dedupe_across_drives && matches!(self.hash_algo, HashAlgo::Md5 | HashAlgo::Crc32) {
+            bail!("--dedupe-across-drives needs a sha256, sha512 or blake3 hash");
+        }
+        // the cursor stands for everything before it in name order, which
+        // only the plain depth-first walk visits in that order; anything
+        // else would leave out what the earlier run never got to
+        if self.resume_from.is_some() {
+            if self.sequential {
+                bail!("--resume-from can't be combined with --sequential");
+            }
+            if self.traversal == Traversal::Bfs {
+                bail!("--resume-from can't be combined with --traversal bfs");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The options that change how files are classified, so a cache of
+    /// classifications is only reused by runs that would come to the same
+    /// results.
+    pub fn classifier_fingerprint(&self) -> String {
+        format!(
+            "read-contents={} inspect-exif={} check-media={} csv-sample-bytes={} csv-skip-lines={}",
+            !self.classify_only,
+            self.inspect_exif,
+            self.check_media,
+            self.csv_sample_bytes,
+            self.csv_skip_lines
+        )
+    }
+
+    /// Folder below the destination root that holds files of `category`.
+    pub fn category_dir<'a>(&'a self, category: &'a str) -> &'a str {
+        self.category_dirs
+            .get(category)
+            .map_or(category, String::as_str)
+    }
+
+    /// Whether a file passes the modification time filters. Directories
+    /// always pass so their contents are still visited.
+    pub fn matches_filters(&self, entry: &DirEntry) -> bool {
+        if self.is_before_cursor(entry.path()) {
+            return false;
+        }
+        if entry.file_type().is_dir() {
+            return true;
+        }
+
+        let Some(newer_than) = self.newer_than else {
+            return true;
+        };
+
+        entry
+            .metadata()
+            .ok()
+            .and_then(|metadata| metadata.modified().ok())
+            .is_some_and(|mtime| mtime > newer_than)
+    }
+
+    /// Whether `path` comes before the `--resume-from` cursor, comparing
+    /// component by component, so that e.g. `a/b/c` comes before `a/c` but
+    /// after `a/b`.
+    pub fn is_before_cursor(&self, path: &Path) -> bool {
+        self.resume_from
+            .as_deref()
+            .is_some_and(|cursor| path < cursor)
+    }
+
+    /// Whether `entry` is a directory with nothing below it at or after the
+    /// `--resume-from` cursor, so the walk needn't go in at all. Directories
+    /// leading to the cursor are still entered.
+    pub fn is_skipped_by_cursor(&self, entry: &DirEntry) -> bool {
+        let path = entry.path();
+        entry.file_type().is_dir()
+            && self
+                .resume_from
+                .as_deref()
+                .is_some_and(|cursor| path < cursor && !cursor.starts_with(path))
+    }
+
+    /// Whether `path` is the destination root or lies below it, which must
+    /// never be scanned into itself.
+    pub fn is_destination(&self, path: &Path) -> bool {
+        self.dest
+            .as_deref()
+            .is_some_and(|dest| path.starts_with(dest))
+    }
+
+    /// Whether `entry` passes `--follow-reparse`: it is no reparse point, or
+    /// one of the listed kinds.
+    pub fn follows_reparse_point(&self, entry: &DirEntry) -> bool {
+        self.follow_reparse.is_none() || self.follows_reparse_tag(entry_reparse_tag(entry))
+    }
+
+    /// Whether an entry with reparse tag `tag`, if any, passes
+    /// `--follow-reparse`.
+    fn follows_reparse_tag(&self, tag: Option<u32>) -> bool {
+        let Some(tags) = &self.follow_reparse else {
+            return true;
+        };
+        tag.is_none_or(|tag| tags.contains(&tag))
+    }
+
+    /// Whether the `--max-runtime` budget is used up.
+    pub fn is_out_of_time(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn csv_sampling(&self) -> CsvSampling {
+        CsvSampling {
+            bytes: self.csv_sample_bytes,
+            skip_lines: self.csv_skip_lines,
+        }
+    }
+
+    /// The longest chain of links the walk and classification follow, from
+    /// `max_symlink_depth`. Without `follow_links` nothing is followed, so
+    /// there is nothing to limit.
+    pub fn symlink_depth_limit(&self) -> Option<usize> {
+        self.max_symlink_depth.filter(|_| self.follow_links)
+    }
+
+    pub(crate) fn classify_settings(&self) -> ClassifySettings {
+        ClassifySettings {
+            read_contents: !self.classify_only,
+            inspect_exif: self.inspect_exif && !self.classify_only,
+            check_media: self.check_media && !self.classify_only,
+            max_symlink_depth: self.symlink_depth_limit(),
+            csv: self.csv_sampling(),
+        }
+    }
+}
+
+/// Builds [`ScanOptions`] for what walking and classifying depend on.
+/// Anything else is set on the built options themselves, as their fields
+/// are public.
+#[derive(Debug, Default)]
+pub struct ScanOptionsBuilder {
+    options: ScanOptions,
+}
+
+impl ScanOptionsBuilder {
+    pub fn roots<I>(mut self, roots: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        self.options.roots = roots.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn dest(mut self, dest: impl Into<PathBuf>) -> Self {
+        self.options.dest = Some(dest.into());
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.options.layout = layout;
+        self
+    }
+
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.options.follow_links = follow_links;
+        self
+    }
+
+    pub fn max_symlink_depth(mut self, depth: usize) -> Self {
+        self.options.max_symlink_depth = Some(depth);
+        self
+    }
+
+    pub fn follow_junctions(mut self, follow_junctions: bool) -> Self {
+        self.options.follow_junctions = follow_junctions;
+        self
+    }
+
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.options.one_file_system = one_file_system;
+        self
+    }
+
+    pub fn include_temp(mut self, include_temp: bool) -> Self {
+        self.options.include_temp = include_temp;
+        self
+    }
+
+    pub fn include_recycle_bin(mut self, include_recycle_bin: bool) -> Self {
+        self.options.include_recycle_bin = include_recycle_bin;
+        self
+    }
+
+    pub fn skip_bak(mut self, skip_bak: bool) -> Self {
+        self.options.skip_bak = skip_bak;
+        self
+    }
+
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.options.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn skip_system(mut self, skip_system: bool) -> Self {
+        self.options.skip_system = skip_system;
+        self
+    }
+
+    pub fn skip_vcs_internals(mut self, skip_vcs_internals: bool) -> Self {
+        self.options.skip_vcs_internals = skip_vcs_internals;
+        self
+    }
+
+    pub fn newer_than(mut self, newer_than: SystemTime) -> Self {
+        self.options.newer_than = Some(newer_than);
+        self
+    }
+
+    pub fn empty_files(mut self, empty_files: EmptyFiles) -> Self {
+        self.options.empty_files = empty_files;
+        self
+    }
+
+    /// Classify by name alone, see [`ScanOptions::classify_only`].
+    pub fn classify_only(mut self, classify_only: bool) -> Self {
+        self.options.classify_only = classify_only;
+        self
+    }
+
+    pub fn csv_sampling(mut self, csv: CsvSampling) -> Self {
+        self.options.csv_sample_bytes = csv.bytes;
+        self.options.csv_skip_lines = csv.skip_lines;
+        self
+    }
+
+    pub fn inspect_exif(mut self, inspect_exif: bool) -> Self {
+        self.options.inspect_exif = inspect_exif;
+        self
+    }
+
+    pub fn check_media(mut self, check_media: bool) -> Self {
+        self.options.check_media = check_media;
+        self
+    }
+
+    pub fn hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.options.hash_algo = hash_algo;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.options.jobs = jobs;
+        self
+    }
+
+    pub fn build(self) -> ScanOptions {
+        self.options
+    }
+}
+
+/// How much of a CSV file is sampled by default, 64 KiB.
+pub const DEFAULT_CSV_SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// The part of a CSV file its separator is detected from, handed down
+/// through classification. Small enough to copy into the thread reading a
+/// file under `--file-timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsvSampling {
+    /// Most bytes read, from `--csv-sample-bytes`.
+    pub bytes: u64,
+    /// Lines read past first, from `--csv-skip-lines`.
+    pub skip_lines: usize,
+}
+
+/// What classification looks at beyond names, taken from [`ScanOptions`]
+/// and handed down through it like [`CsvSampling`], which it carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ClassifySettings {
+    /// Cleared by `--classify-only`, which leaves every file unopened.
+    pub read_contents: bool,
+    /// `--inspect-exif`, never set without `read_contents`.
+    pub inspect_exif: bool,
+    /// `--check-media`, never set without `read_contents`.
+    pub check_media: bool,
+    /// `--max-symlink-depth` while following links.
+    pub max_symlink_depth: Option<usize>,
+    pub csv: CsvSampling,
+}
+
+impl Default for CsvSampling {
+    fn default() -> Self {
+        Self {
+            bytes: DEFAULT_CSV_SAMPLE_BYTES,
+            skip_lines: 0,
+        }
+    }
+}
+
+/// How often `--progress-to` writes a line by default.
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many directories `--dir-sizes` lists by default.
+pub const DEFAULT_DIR_SIZES_TOP: usize = 20;
+
+/// Parses a byte count such as `4096`, `500M`, `1.5GiB` or `10GB`.
+///
+/// Suffixes without a trailing `B` and the `iB` forms are binary multiples
+/// (`K` = 1024), the plain `B` forms are decimal (`KB` = 1000).
+pub fn parse_size(text: &str) -> anyhow::Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("invalid size `{}`", text))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, decimal) = match unit.strip_suffix("IB") {
+        Some(prefix) => (prefix, false),
+        None => match unit.strip_suffix('B') {
+            Some(prefix) => (prefix, !prefix.is_empty()),
+            None => (unit.as_str(), false),
+        },
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => bail!("unknown size unit in `{}`", text),
+    };
+    let base: f64 = if decimal { 1000.0 } else { 1024.0 };
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok((number * base.powi(exponent)) as u64)
+}
+
+/// Rejects paths that are absolute or could climb out of the directory they
+/// are joined onto.
+pub fn ensure_contained(path: &Path) -> anyhow::Result<()> {
+    if path.as_os_str().is_empty() {
+        bail!("path is empty");
+    }
+
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            Component::ParentDir => bail!("`{}` escapes the destination", path.display()),
+            Component::RootDir | Component::Prefix(_) => {
+                bail!("`{}` is not a relative path", path.display())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Persistent settings from `config.toml`, each of which a command-line flag
+/// overrides.
+///
+/// Keys are named like the flags without the leading dashes, e.g.
+/// `one-file-system = true`, with `[category-dirs]` as a table. Relative
+/// paths are resolved against the directory holding the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub roots: Vec<PathBuf>,
+    pub dest: Option<PathBuf>,
+    pub layout: Option<Layout>,
+    pub manifest_format: Option<ManifestFormat>,
+    pub category_dirs: HashMap<String, String>,
+    pub on_illegal_name: Option<IllegalNames>,
+    pub on_dest_error: Option<DestErrors>,
+    pub one_file_system: bool,
+    pub drive_label: bool,
+    pub include_temp: bool,
+    pub include_recycle_bin: bool,
+    pub skip_bak: Option<bool>,
+    pub follow_links: Option<bool>,
+    pub max_symlink_depth: Option<usize>,
+    pub follow_junctions: Option<bool>,
+    pub follow_reparse: Option<Vec<String>>,
+    pub cache: Option<PathBuf>,
+    pub no_cache: bool,
+    pub newer_than: Option<PathBuf>,
+    pub empty_files: Option<EmptyFiles>,
+    pub rules: Option<PathBuf>,
+    pub progress: bool,
+    pub count_first: bool,
+    pub skip_vcs_internals: bool,
+    pub min_free: Option<String>,
+    pub compress_level: Option<String>,
+    pub csv_sample_bytes: Option<String>,
+    pub csv_skip_lines: Option<usize>,
+    pub skip_hidden: bool,
+    pub skip_system: bool,
+    pub verbose: bool,
+    pub format: Option<OutputFormat>,
+    pub flatten_single_child_dirs: bool,
+    pub human: bool,
+    pub si: bool,
+    pub sequential: bool,
+    pub hash_algo: Option<HashAlgo>,
+    pub verify_after: bool,
+    pub inspect_archives: bool,
+    pub check_crc: bool,
+    pub check_media: bool,
+    pub inspect_certs: bool,
+    pub inspect_exif: bool,
+    pub mount_images: bool,
+    pub scan_archives: bool,
+    pub dedupe_across_drives: bool,
+    pub backup_streams: bool,
+    pub traversal: Option<Traversal>,
+    pub skip_dirs_over: Option<usize>,
+    pub large_dirs: Option<LargeDirs>,
+    pub max_runtime: Option<String>,
+    pub file_timeout: Option<String>,
+    pub progress_to: Option<PathBuf>,
+    pub progress_interval: Option<String>,
+    pub relative_to: Option<PathBuf>,
+    pub preserve_permissions: bool,
+    pub use_vss: bool,
+    pub jobs: Option<NonZeroUsize>,
+    pub max_parallel_drives: Option<NonZeroUsize>,
+    pub readahead: Option<NonZeroUsize>,
+    pub summary_only: bool,
+    pub classification_stats_per_drive: bool,
+    pub show_regular: bool,
+    pub by_extension: bool,
+    pub magic_report: bool,
+    pub sort_extensions: Option<ExtensionOrder>,
+    pub dir_sizes: bool,
+    pub dir_sizes_top: Option<usize>,
+    pub stats_json: Option<PathBuf>,
+    pub classify_only: bool,
+    pub template: Option<String>,
+}
+
+/// Loads the settings file at `path`, or from the default location if no
+/// path is given. A missing default file yields the defaults, a missing
+/// explicit one is an error.
+pub fn load_config(path: Option<&Path>) -> anyhow::Result<ConfigFile> {
+    let (path, explicit) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => (config_dir().join("config.toml"), false),
+    };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound && !explicit => {
+            return Ok(ConfigFile::default())
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read `{}`", path.display()))
+        }
+    };
+
+    // the toml error already points at the offending line and column
+    let mut file: ConfigFile =
+        toml::from_str(&text).with_context(|| format!("invalid config `{}`", path.display()))?;
+
+    if let Some(base) = path.parent() {
+        for path in file
+            .roots
+            .iter_mut()
+            .chain(&mut file.dest)
+            .chain(&mut file.cache)
+            .chain(&mut file.newer_than)
+            .chain(&mut file.rules)
+            .chain(&mut file.relative_to)
+            .chain(&mut file.stats_json)
+            .chain(&mut file.progress_to)
+        {
+            *path = base.join(&*path);
+        }
+    }
+
+    Ok(file)
+}
+
+/// Parses a duration such as `90`, `45s`, `30m`, `2h` or `1h30m`. A bare
+/// number counts seconds.
+pub fn parse_duration(text: &str) -> anyhow::Result<Duration> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        bail!("empty duration");
+    }
+
+    let mut total = 0u64;
+    let mut rest = trimmed;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: u64 = number
+            .parse()
+            .with_context(|| format!("invalid duration `{}`", text))?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let seconds = match unit.trim() {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => bail!("unknown duration unit in `{}`", text),
+        };
+        total = number
+            .checked_mul(seconds)
+            .and_then(|seconds| total.checked_add(seconds))
+            .with_context(|| format!("duration `{}` is too long", text))?;
+        rest = tail;
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempTree;
+
+    fn with_category_dir(category: &str, dir: &str) -> ScanOptions {
+        ScanOptions {
+            category_dirs: HashMap::from([(category.to_owned(), dir.to_owned())]),
+            ..ScanOptions::default()
+        }
+    }
+
+    #[test]
+    fn mapped_categories_use_their_folder_others_their_name() {
+        let config = with_category_dir("secrets", "sensitive");
+        assert_eq!(config.category_dir("secrets"), "sensitive");
+        assert_eq!(config.category_dir("databases"), "databases");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn category_dirs_must_stay_inside_the_destination() {
+        for dir in ["../outside", "a/../../b", "/abs", ""] {
+            assert!(
+                with_category_dir("secrets", dir).validate().is_err(),
+                "{}",
+                dir
+            );
+        }
+        assert!(with_category_dir("nonsense", "x").validate().is_err());
+    }
+
+    #[test]
+    fn everything_below_the_destination_is_the_destination() {
+        let config = ScanOptions {
+            dest: Some(PathBuf::from("/data/backup")),
+            ..ScanOptions::default()
+        };
+        assert!(config.is_destination(Path::new("/data/backup")));
+        assert!(config.is_destination(Path::new("/data/backup/manifest.json")));
+        assert!(!config.is_destination(Path::new("/data/backup-old")));
+        assert!(!config.is_destination(Path::new("/data")));
+    }
+
+    #[test]
+    fn durations_add_up_their_units() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration(" 2d ").unwrap(),
+            Duration::from_secs(172_800)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn durations_that_overflow_are_rejected() {
+        let error = parse_duration("18446744073709551615d").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "duration `18446744073709551615d` is too long"
+        );
+        let error = parse_duration("18446744073709551615s1s").unwrap_err();
+        assert!(error.to_string().ends_with("is too long"));
+    }
+
+    #[test]
+    fn paths_in_the_settings_file_are_relative_to_it() {
+        let tree = TempTree::new();
+        let path = tree.file(
+            "settings/config.toml",
+            "roots = [\"docs\", \"/abs\"]\ndest = \"../backup\"\nstats-json = \"stats.json\"\n\
+             progress-to = \"logs/progress.log\"\n",
+        );
+        let file = load_config(Some(&path)).unwrap();
+        let base = tree.path().join("settings");
+        assert_eq!(file.roots, [base.join("docs"), PathBuf::from("/abs")]);
+        assert_eq!(file.dest, Some(base.join("../backup")));
+        assert_eq!(file.stats_json, Some(base.join("stats.json")));
+        assert_eq!(file.progress_to, Some(base.join("logs/progress.log")));
+    }
+
+    #[test]
+    fn settings_file_errors_point_at_the_line() {
+        let tree = TempTree::new();
+        let path = tree.file("config.toml", "dest = \"out\"\nno-such-key = 1\n");
+        let error = format!("{:#}", load_config(Some(&path)).unwrap_err());
+        assert!(error.contains("line 2"), "{}", error);
+
+        let missing = tree.path().join("missing.toml");
+        assert!(load_config(Some(&missing)).is_err());
+    }
+
+    #[test]
+    fn sizes_switch_units_at_the_base() {
+        for (bytes, binary, si) in [
+            (0, "0 B", "0 B"),
+            (999, "999 B", "999 B"),
+            (1000, "1000 B", "1.0 kB"),
+            (1023, "1023 B", "1.0 kB"),
+            (1024, "1.0 KiB", "1.0 kB"),
+            (1536, "1.5 KiB", "1.5 kB"),
+            (1_048_575, "1.0 MiB", "1.0 MB"),
+            (1_048_576, "1.0 MiB", "1.0 MB"),
+            (1_610_612_736, "1.5 GiB", "1.6 GB"),
+            (u64::MAX, "16.0 EiB", "18.4 EB"),
+        ] {
+            assert_eq!(format_size(bytes, true), binary, "{bytes} in binary units");
+            assert_eq!(format_size(bytes, false), si, "{bytes} in decimal units");
+        }
+    }
+
+    #[test]
+    fn raw_byte_counts_stay_the_default() {
+        assert_eq!(SizeFormat::Bytes.amount(1_048_576), "1048576");
+        assert_eq!(SizeFormat::Bytes.describe(1024), "1024 bytes");
+        assert_eq!(SizeFormat::Binary.describe(1024), "1.0 KiB");
+        assert_eq!(SizeFormat::Si.amount(1024), "1.0 kB");
+    }
+
+    #[test]
+    fn the_cursor_compares_paths_component_by_component() {
+        let config = ScanOptions {
+            resume_from: Some(PathBuf::from("home/a/b")),
+            ..ScanOptions::default()
+        };
+        assert!(config.is_before_cursor(Path::new("home/a")));
+        assert!(config.is_before_cursor(Path::new("home/a/a/z")));
+        // `a-b` sorts after `a/b` by bytes, but `a` comes before `a-b`
+        assert!(config.is_before_cursor(Path::new("home/a/a-b")));
+        assert!(!config.is_before_cursor(Path::new("home/a/b")));
+        assert!(!config.is_before_cursor(Path::new("home/a/b/c")));
+        assert!(!config.is_before_cursor(Path::new("home/a/c")));
+        assert!(!config.is_before_cursor(Path::new("home/b")));
+        assert!(!ScanOptions::default().is_before_cursor(Path::new("home/a")));
+    }
+
+    #[test]
+    fn the_cursor_needs_a_walk_in_name_order() {
+        let resumed = |sequential, traversal| ScanOptions {
+            resume_from: Some(PathBuf::from("home/a/b")),
+            sequential,
+            traversal,
+            ..ScanOptions::default()
+        };
+        resumed(false, Traversal::Dfs).validate().unwrap();
+        let error = resumed(true, Traversal::Dfs).validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--resume-from can't be combined with --sequential"
+        );
+        let error = resumed(false, Traversal::Bfs).validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--resume-from can't be combined with --traversal bfs"
+        );
+        assert!(resumed(true, Traversal::Bfs).validate().is_err());
+
+        // without a cursor the walk may go in any order
+        let unresumed = ScanOptions {
+            resume_from: None,
+            ..resumed(true, Traversal::Bfs)
+        };
+        unresumed.validate().unwrap();
+    }
+
+    #[test]
+    fn only_listed_reparse_tags_are_followed() {
+        use crate::platform::{parse_reparse_tag, IO_REPARSE_TAG_CLOUD, IO_REPARSE_TAG_DEDUP};
+
+        let config = ScanOptions {
+            follow_reparse: Some(vec![parse_reparse_tag("dedup").unwrap()]),
+            ..ScanOptions::default()
+        };
+        assert!(config.follows_reparse_tag(Some(IO_REPARSE_TAG_DEDUP)));
+        assert!(!config.follows_reparse_tag(Some(IO_REPARSE_TAG_CLOUD)));
+        // anything that isn't a reparse point is walked as always
+        assert!(config.follows_reparse_tag(None));
+
+        // without the option nothing is held back
+        let config = ScanOptions::default();
+        assert!(config.follows_reparse_tag(Some(IO_REPARSE_TAG_CLOUD)));
+        assert!(config.follows_reparse_point(&crate::testing::entry(Path::new("."))));
+    }
+
+    #[test]
+    fn built_options_start_from_the_defaults() {
+        let options = ScanOptions::builder().build();
+
+        assert!(options.roots.is_empty() && options.dest.is_none());
+        assert_eq!(options.layout, Layout::Mirror);
+        assert!(options.follow_links && options.follow_junctions);
+        assert_eq!(options.max_symlink_depth, None);
+        assert_eq!(options.empty_files, EmptyFiles::Backup);
+        assert_eq!(options.csv_sampling(), CsvSampling::default());
+        assert_eq!(options.hash_algo, HashAlgo::Sha256);
+        assert_eq!(options.jobs, 1);
+        assert!(!options.classify_only && !options.skip_hidden && !options.include_temp);
+        assert_eq!(
+            options.classifier_fingerprint(),
+            ScanOptions::default().classifier_fingerprint()
+        );
+        options.validate().unwrap();
+    }
+
+    #[test]
+    fn builder_settings_reach_classification() {
+        let by_name = ScanOptions::builder()
+            .classify_only(true)
+            .inspect_exif(true)
+            .check_media(true)
+            .max_symlink_depth(2)
+            .build();
+        assert_eq!(
+            by_name.classify_settings(),
+            ClassifySettings {
+                read_contents: false,
+                inspect_exif: false,
+                check_media: false,
+                max_symlink_depth: Some(2),
+                csv: CsvSampling::default(),
+            }
+        );
+
+        let reading = ScanOptions::builder()
+            .inspect_exif(true)
+            .check_media(true)
+            .max_symlink_depth(2)
+            .follow_links(false)
+            .build()
+            .classify_settings();
+        assert!(reading.read_contents && reading.inspect_exif && reading.check_media);
+        // links that aren't followed have no chain to limit
+        assert_eq!(reading.max_symlink_depth, None);
+    }
+
+    #[test]
+    fn builder_sets_each_option() {
+        let newer_than = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let csv = CsvSampling {
+            bytes: 512,
+            skip_lines: 2,
+        };
+        let options = ScanOptions::builder()
+            .roots(["/home", "/srv"])
+            .dest("/mnt/backup")
+            .layout(Layout::ByCategory)
+            .follow_links(false)
+            .max_symlink_depth(5)
+            .follow_junctions(false)
+            .one_file_system(true)
+            .include_temp(true)
+            .include_recycle_bin(true)
+            .skip_bak(true)
+            .skip_hidden(true)
+            .skip_system(true)
+            .skip_vcs_internals(true)
+            .newer_than(newer_than)
+            .empty_files(EmptyFiles::Skip)
+            .classify_only(true)
+            .csv_sampling(csv)
+            .inspect_exif(true)
+            .check_media(true)
+            .hash_algo(HashAlgo::Blake3)
+            .jobs(4)
+            .build();
+
+        assert_eq!(options.roots, [Path::new("/home"), Path::new("/srv")]);
+        assert_eq!(options.dest.as_deref(), Some(Path::new("/mnt/backup")));
+        assert_eq!(options.layout, Layout::ByCategory);
+        assert!(!options.follow_links && !options.follow_junctions);
+        assert_eq!(options.max_symlink_depth, Some(5));
+        assert!(options.one_file_system && options.include_temp && options.include_recycle_bin);
+        assert!(options.skip_bak && options.skip_hidden && options.skip_system);
+        assert!(options.skip_vcs_internals);
+        assert_eq!(options.newer_than, Some(newer_than));
+        assert_eq!(options.empty_files, EmptyFiles::Skip);
+        assert!(options.classify_only && options.inspect_exif && options.check_media);
+        assert_eq!(options.csv_sampling(), csv);
+        assert_eq!(options.hash_algo, HashAlgo::Blake3);
+        assert_eq!(options.jobs, 4);
+        options.validate().unwrap();
+    }
+}
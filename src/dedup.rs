@@ -0,0 +1,277 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    config::SizeFormat,
+    hash::{hash_file, HashAlgo},
+    platform::DeviceId,
+};
+
+/// How many of the biggest duplicate groups the report lists.
+const LARGEST_GROUPS: usize = 10;
+
+/// Files with identical contents.
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub hash: String,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Bytes that storing a single copy would save.
+    pub fn redundant_bytes(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+#[derive(Serialize)]
+pub struct DedupSummary {
+    pub duplicate_sets: usize,
+    pub redundant_bytes: u64,
+    /// The groups wasting the most space, biggest first.
+    pub largest: Vec<DuplicateGroup>,
+}
+
+/// Collects files for `--dedup-report`. Only files sharing a size can be
+/// identical, so hashing is put off until the end and limited to those.
+#[derive(Default)]
+pub struct DedupReport {
+    by_size: HashMap<u64, Vec<PathBuf>>,
+}
+
+impl DedupReport {
+    pub fn merge(&mut self, other: DedupReport) {
+        for (size, paths) in other.by_size {
+            self.by_size.entry(size).or_default().extend(paths);
+        }
+    }
+
+    pub fn add(&mut self, path: PathBuf, size: u64) {
+        // empty files are all "identical" but free to store
+        if size > 0 {
+            self.by_size.entry(size).or_default().push(path);
+        }
+    }
+
+    /// Hashes the size collisions and groups them by digest. Files that
+    /// can't be read are left out.
+    pub fn summarize(self, algo: HashAlgo) -> DedupSummary {
+        let mut groups = Vec::new();
+        for (size, paths) in self.by_size {
+            if paths.len() < 2 {
+                continue;
+            }
+
+            let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+            for path in paths {
+                if let Ok(hash) = hash_file(&path, algo) {
+                    by_hash.entry(hash).or_default().push(path);
+                }
+            }
+            groups.extend(
+                by_hash
+                    .into_iter()
+                    .filter(|(_, paths)| paths.len() > 1)
+                    .map(|(hash, mut paths)| {
+                        paths.sort();
+                        DuplicateGroup { size, hash, paths }
+                    }),
+            );
+        }
+
+        groups.sort_by_key(|group| std::cmp::Reverse(group.redundant_bytes()));
+        let duplicate_sets = groups.len();
+        let redundant_bytes = groups.iter().map(DuplicateGroup::redundant_bytes).sum();
+        groups.truncate(LARGEST_GROUPS);
+
+        DedupSummary {
+            duplicate_sets,
+            redundant_bytes,
+            largest: groups,
+        }
+    }
+}
+
+impl DedupSummary {
+    pub fn print(&self, sizes: SizeFormat) {
+        let redundant = match sizes {
+            SizeFormat::Bytes => format!("{} redundant bytes", self.redundant_bytes),
+            _ => format!("{} redundant", sizes.amount(self.redundant_bytes)),
+        };
+        println!("{} duplicate sets, {}", self.duplicate_sets, redundant);
+        for group in &self.largest {
+            println!(
+                "\n{} copies of {}, {} redundant:",
+                group.paths.len(),
+                sizes.describe(group.size),
+                sizes.amount(group.redundant_bytes())
+            );
+            for path in &group.paths {
+                println!("  {}", path.display());
+            }
+        }
+    }
+}
+
+/// What `--dedupe-across-drives` saved by linking instead of copying.
+#[derive(Clone, Default, Serialize)]
+pub struct DedupSavings {
+    /// Copies that are links to an earlier one.
+    pub linked: u64,
+    pub linked_bytes: u64,
+    /// Those of them whose earlier copy came from another drive.
+    pub across_drives: u64,
+    pub across_drives_bytes: u64,
+}
+
+impl DedupSavings {
+    pub fn print(&self, sizes: SizeFormat) {
+        eprintln!(
+            "linked {} duplicate files instead of copying them ({}), {} of them from other \
+             drives ({})",
+            self.linked,
+            sizes.describe(self.linked_bytes),
+            self.across_drives,
+            sizes.describe(self.across_drives_bytes)
+        );
+    }
+}
+
+/// The first copy of each content a run wrote, shared by the workers of
+/// every drive for `--dedupe-across-drives`. Files are hashed before the
+/// index is asked, so the lock is only held for a lookup.
+#[derive(Default)]
+pub struct ContentIndex {
+    inner: Mutex<IndexState>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    /// By size, hash and whether the copy is gzipped, as a gzipped copy
+    /// can't stand in for a plain one.
+    copies: HashMap<(u64, String, bool), (PathBuf, Option<DeviceId>)>,
+    savings: DedupSavings,
+}
+
+impl ContentIndex {
+    fn state(&self) -> std::sync::MutexGuard<'_, IndexState> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The earlier copy of these contents and the drive it came from, or
+    /// nothing if there is none, in which case `target` becomes the copy
+    /// later ones are linked to.
+    pub fn claim(
+        &self,
+        size: u64,
+        hash: &str,
+        compressed: bool,
+        target: &Path,
+        drive: Option<DeviceId>,
+    ) -> Option<(PathBuf, Option<DeviceId>)> {
+        let mut state = self.state();
+        let key = (size, hash.to_owned(), compressed);
+        if let Some(copy) = state.copies.get(&key) {
+            return Some(copy.clone());
+        }
+        state.copies.insert(key, (target.to_owned(), drive));
+        None
+    }
+
+    /// Counts a copy that was linked to an earlier one.
+    pub fn record_link(&self, size: u64, across_drives: bool) {
+        let savings = &mut self.state().savings;
+        savings.linked += 1;
+        savings.linked_bytes += size;
+        if across_drives {
+            savings.across_drives += 1;
+            savings.across_drives_bytes += size;
+        }
+    }
+
+    pub fn savings(&self) -> DedupSavings {
+        self.state().savings.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ScanOptions,
+        testing::{scan, serial, TempTree},
+    };
+
+    #[test]
+    fn savings_are_the_size_of_every_copy_but_one() {
+        let tree = TempTree::new();
+        let mut report = DedupReport::default();
+        let mut add = |name: &str, contents: &[u8]| {
+            report.add(tree.file(name, contents), contents.len() as u64);
+        };
+        for name in ["a.bin", "b/a.bin", "c/a.bin"] {
+            add(name, &[1; 100]);
+        }
+        add("d.bin", &[2; 50]);
+        add("e/d.bin", &[2; 50]);
+        // same size but different contents
+        add("f.bin", &[3; 50]);
+        add("empty", b"");
+        add("also-empty", b"");
+
+        let summary = report.summarize(HashAlgo::Sha256);
+        assert_eq!(summary.duplicate_sets, 2);
+        // two of three 100 byte copies and one of two 50 byte ones
+        assert_eq!(summary.redundant_bytes, 2 * 100 + 50);
+        assert_eq!(summary.largest[0].size, 100);
+        assert_eq!(summary.largest[0].paths.len(), 3);
+        assert_eq!(summary.largest[1].paths.len(), 2);
+    }
+
+    #[test]
+    fn dedup_reports_copy_nothing() {
+        let _serial = serial();
+        let tree = TempTree::new();
+        tree.file("src/a/.env", "KEY=value\n");
+        tree.file("src/b/.env", "KEY=value\n");
+        let state = scan(&ScanOptions {
+            roots: vec![tree.path().join("src")],
+            dest: Some(tree.path().join("dest")),
+            dedup_report: true,
+            ..ScanOptions::default()
+        });
+
+        assert!(state.manifest.entries.is_empty());
+        let summary = state.dedup.unwrap().summarize(HashAlgo::Sha256);
+        assert_eq!(summary.redundant_bytes, 10);
+    }
+
+    #[test]
+    fn the_first_copy_of_each_content_is_claimed() {
+        let index = ContentIndex::default();
+        let (first, second) = (Path::new("dest/a"), Path::new("dest/b"));
+
+        assert_eq!(index.claim(10, "abc", false, first, Some(1)), None);
+        assert_eq!(
+            index.claim(10, "abc", false, second, Some(2)),
+            Some((first.to_owned(), Some(1)))
+        );
+        // a gzipped copy doesn't stand in for a plain one
+        assert_eq!(index.claim(10, "abc", true, second, Some(2)), None);
+        assert_eq!(index.claim(11, "abc", false, second, Some(2)), None);
+
+        index.record_link(10, false);
+        index.record_link(20, true);
+        let savings = index.savings();
+        assert_eq!((savings.linked, savings.linked_bytes), (2, 30));
+        assert_eq!(
+            (savings.across_drives, savings.across_drives_bytes),
+            (1, 20)
+        );
+    }
+}
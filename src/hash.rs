@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::{
+    fmt::Write,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Digest recorded for every copied file in the manifest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+    Blake3,
+    /// Fast but broken, only for matching checksums from other tools.
+    Md5,
+    /// Catches accidental corruption, nothing more.
+    Crc32,
+}
+
+/// Incremental state for one of the [`HashAlgo`]s.
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Md5(md5::Md5),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            HashAlgo::Sha512 => Self::Sha512(sha2::Sha512::new()),
+            HashAlgo::Blake3 => Self::Blake3(Box::default()),
+            HashAlgo::Md5 => Self::Md5(md5::Md5::new()),
+            HashAlgo::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Crc32(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Self::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Self::Sha512(hasher) => to_hex(&hasher.finalize()),
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Md5(hasher) => to_hex(&hasher.finalize()),
+            Self::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
+
+/// Hashes the file's contents, returning the digest as lowercase hex.
+pub fn hash_file(path: &Path, algo: HashAlgo) -> io::Result<String> {
+    hash_reader(File::open(path)?, algo)
+}
+
+/// Like [`hash_file`], for contents that don't come straight from a file.
+pub fn hash_reader(mut reader: impl Read, algo: HashAlgo) -> io::Result<String> {
+    let mut hasher = Hasher::new(algo);
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => hasher.update(&buf[..len]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_the_reference_values() {
+        let digest = |algo| hash_reader(&b"abc"[..], algo).unwrap();
+        assert_eq!(
+            digest(HashAlgo::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(HashAlgo::Sha512),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            digest(HashAlgo::Blake3),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(digest(HashAlgo::Md5), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest(HashAlgo::Crc32), "352441c2");
+    }
+
+    #[test]
+    fn files_hash_like_their_contents() {
+        let tree = crate::testing::TempTree::new();
+        let path = tree.file("abc.txt", "abc");
+        assert_eq!(
+            hash_file(&path, HashAlgo::Md5).unwrap(),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+    }
+}
@@ -0,0 +1,810 @@
+use anyhow::Context;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compress::open_copy,
+    config::{ManifestFormat, SizeFormat},
+    hash::{hash_reader, HashAlgo},
+    platform::{apply_permissions, evict_cached, UnixPermissions, VolumeInfo},
+};
+
+/// Name of the manifest file written to the destination root.
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// Names of the extra manifests of `--manifest-format`.
+const CSV_MANIFEST_NAME: &str = "manifest.csv";
+const SQLITE_MANIFEST_NAME: &str = "manifest.sqlite";
+
+/// A file left out as its copy couldn't be written.
+#[derive(Serialize, Deserialize)]
+pub struct FailedCopy {
+    pub source: PathBuf,
+    pub error: String,
+}
+
+/// A file only one of two manifests has, see [`Manifest::drift_from`].
+#[derive(Serialize)]
+pub struct DriftedFile {
+    pub source: PathBuf,
+    pub size: u64,
+    pub tag: String,
+}
+
+/// A file both manifests have with different contents.
+#[derive(Serialize)]
+pub struct ChangedFile {
+    pub source: PathBuf,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+/// How a backup changed between two manifests of it, for
+/// `--compare-manifests`.
+#[derive(Default, Serialize)]
+pub struct ManifestDrift {
+    pub added: Vec<DriftedFile>,
+    pub removed: Vec<DriftedFile>,
+    pub modified: Vec<ChangedFile>,
+    pub unchanged: u64,
+    /// Change of the total size backed up, in bytes.
+    pub size_delta: i64,
+    /// The manifests were written with different hash algorithms, so only
+    /// sizes tell modified files apart.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub by_size: bool,
+}
+
+impl ManifestDrift {
+    /// Lists the files by how they changed, then the counts and size change
+    /// of each group.
+    pub fn print(&self, sizes: SizeFormat) {
+        let delta = |bytes: i64| {
+            let sign = if bytes < 0 { '-' } else { '+' };
+            format!("{}{}", sign, sizes.describe(bytes.unsigned_abs()))
+        };
+        let total = |files: &[DriftedFile]| files.iter().map(|file| file.size).sum::<u64>();
+
+        for (heading, files) in [("added", &self.added), ("removed", &self.removed)] {
+            if !files.is_empty() {
+                println!("{}", heading);
+            }
+            for file in files {
+                println!(
+                    "  {} # {} ({})",
+                    file.source.display(),
+                    file.tag,
+                    sizes.describe(file.size)
+                );
+            }
+        }
+        if !self.modified.is_empty() {
+            println!("modified");
+        }
+        for file in &self.modified {
+            println!(
+                "  {} ({} -> {})",
+                file.source.display(),
+                sizes.describe(file.old_size),
+                sizes.describe(file.new_size)
+            );
+        }
+
+        let modified = self
+            .modified
+            .iter()
+            .map(|file| file.new_size as i64 - file.old_size as i64)
+            .sum();
+        println!(
+            "added     {:>10}  {}",
+            self.added.len(),
+            delta(total(&self.added) as i64)
+        );
+        println!(
+            "removed   {:>10}  {}",
+            self.removed.len(),
+            delta(-(total(&self.removed) as i64))
+        );
+        println!("modified  {:>10}  {}", self.modified.len(), delta(modified));
+        println!("unchanged {:>10}", self.unchanged);
+        println!("{:<22}{}", "total", delta(self.size_delta));
+        if self.by_size {
+            eprintln!(
+                "note: the manifests use different hash algorithms, files were compared by size"
+            );
+        }
+    }
+}
+
+/// One file copied into the destination.
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Where the file was read from, relative to the manifest's
+    /// `relative_to` root when it lies below it.
+    pub source: PathBuf,
+    /// Where the copy lives, relative to the destination root.
+    pub path: PathBuf,
+    pub size: u64,
+    /// Digest of the source contents, computed with the manifest's algorithm.
+    pub hash: String,
+    /// The classification tag, as in the text output.
+    pub tag: String,
+    /// Category of the classification, absent for files without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// The copy is gzipped, see `--compress-level`. `size` and `hash`
+    /// still describe the original.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Mode and ownership of the source, recorded on Unix with
+    /// `--preserve-permissions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<UnixPermissions>,
+}
+
+/// Record of everything a run copied, kept next to the copies so the backup
+/// can be checked later.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub hash_algo: HashAlgo,
+    /// Root that relative sources were taken against, from `--relative-to`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_to: Option<PathBuf>,
+    pub entries: Vec<ManifestEntry>,
+    /// Sources that were held open by another process and couldn't be
+    /// copied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locked: Vec<PathBuf>,
+    /// Sources whose copy couldn't be written, under `--on-dest-error
+    /// skip`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedCopy>,
+    /// The volume of each scanned root, with `--drive-label`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub volumes: BTreeMap<PathBuf, VolumeInfo>,
+}
+
+impl Manifest {
+    pub fn new(hash_algo: HashAlgo, relative_to: Option<PathBuf>) -> Self {
+        Self {
+            hash_algo,
+            relative_to,
+            entries: Vec::new(),
+            locked: Vec::new(),
+            failed: Vec::new(),
+            volumes: BTreeMap::new(),
+        }
+    }
+
+    /// Appends what another scan of the same run copied.
+    pub fn merge(&mut self, other: Manifest) {
+        self.entries.extend(other.entries);
+        self.locked.extend(other.locked);
+        self.failed.extend(other.failed);
+        self.volumes.extend(other.volumes);
+    }
+
+    /// Where an entry's source lives, joining relative sources against
+    /// `base`, or the recorded root if no base is given. Sources outside
+    /// the root were stored absolute, drive included, and are used as is.
+    pub fn source_path(&self, entry: &ManifestEntry, base: Option<&Path>) -> PathBuf {
+        match base.or(self.relative_to.as_deref()) {
+            Some(base) if entry.source.is_relative() => base.join(&entry.source),
+            _ => entry.source.clone(),
+        }
+    }
+
+    /// What changed from the `old` manifest to this one, matching files by
+    /// their source. Contents are compared by hash, or by size if the
+    /// manifests weren't written with the same algorithm.
+    pub fn drift_from(&self, old: &Manifest) -> ManifestDrift {
+        let (before, after) = (old.by_source(), self.by_source());
+        let by_size = old.hash_algo != self.hash_algo;
+        let mut drift = ManifestDrift {
+            by_size,
+            ..ManifestDrift::default()
+        };
+
+        for (source, entry) in &after {
+            match before.get(source) {
+                None => drift.added.push(DriftedFile {
+                    source: source.clone(),
+                    size: entry.size,
+                    tag: entry.tag.clone(),
+                }),
+                Some(previous)
+                    if previous.size != entry.size || (!by_size && previous.hash != entry.hash) =>
+                {
+                    drift.modified.push(ChangedFile {
+                        source: source.clone(),
+                        old_size: previous.size,
+                        new_size: entry.size,
+                    });
+                }
+                Some(_) => drift.unchanged += 1,
+            }
+        }
+        for (source, entry) in &before {
+            if !after.contains_key(source) {
+                drift.removed.push(DriftedFile {
+                    source: source.clone(),
+                    size: entry.size,
+                    tag: entry.tag.clone(),
+                });
+            }
+        }
+
+        let size = |entries: &BTreeMap<PathBuf, &ManifestEntry>| {
+            entries.values().map(|entry| entry.size as i64).sum::<i64>()
+        };
+        drift.size_delta = size(&after) - size(&before);
+        drift
+    }
+
+    /// The entries by where their source lives, the last one for sources
+    /// copied more than once.
+    fn by_source(&self) -> BTreeMap<PathBuf, &ManifestEntry> {
+        self.entries
+            .iter()
+            .map(|entry| (self.source_path(entry, None), entry))
+            .collect()
+    }
+
+    /// Copies every file from the backup in `dest` back to its source
+    /// location, see [`Self::source_path`]. Existing files are left alone.
+    /// With `preserve_permissions`, recorded modes and owners are applied
+    /// to the restored files.
+    /// Prints each file and returns how many couldn't be restored.
+    pub fn restore(&self, dest: &Path, base: Option<&Path>, preserve_permissions: bool) -> usize {
+        let mut failed = 0;
+
+        for entry in &self.entries {
+            let target = self.source_path(entry, base);
+            if target.exists() {
+                failed += 1;
+                println!("{} # exists, not overwritten", target.display());
+                continue;
+            }
+
+            let copied = target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| {
+                    let copy = dest.join(&entry.path);
+                    if !entry.compressed {
+                        return fs::copy(copy, &target);
+                    }
+                    io::copy(
+                        &mut open_copy(&copy, true)?,
+                        &mut fs::File::create(&target)?,
+                    )
+                })
+                .and_then(|_| match &entry.permissions {
+                    Some(permissions) if preserve_permissions => {
+                        apply_permissions(&target, permissions)
+                    }
+                    _ => Ok(()),
+                });
+            match copied {
+                Ok(_) => println!("{} # restored", target.display()),
+                Err(err) => {
+                    failed += 1;
+                    println!("{} # failed ({})", target.display(), err);
+                }
+            }
+        }
+
+        failed
+    }
+
+    pub fn load(dest: &Path) -> anyhow::Result<Self> {
+        Self::read(&dest.join(MANIFEST_NAME))
+    }
+
+    /// Reads the manifest at `path`, or the one in the backup folder at
+    /// `path`.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        if path.is_dir() {
+            return Self::load(path);
+        }
+        let data =
+            fs::read(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("failed to parse manifest `{}`", path.display()))
+    }
+
+    /// Writes the manifest into `dest`, replacing any previous one, along
+    /// with the extra one of `format`.
+    pub fn save(&self, dest: &Path, format: ManifestFormat) -> anyhow::Result<()> {
+        fs::create_dir_all(dest)
+            .with_context(|| format!("failed to create `{}`", dest.display()))?;
+
+        // the manifest is what makes a partial backup usable, so it must
+        // never be left half-written
+        let path = dest.join(MANIFEST_NAME);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write manifest `{}`", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("failed to write manifest `{}`", path.display()))?;
+
+        let (path, write): (_, fn(&Self, &Path) -> anyhow::Result<()>) = match format {
+            ManifestFormat::Json => return Ok(()),
+            ManifestFormat::Csv => (dest.join(CSV_MANIFEST_NAME), Self::write_csv),
+            ManifestFormat::Sqlite => (dest.join(SQLITE_MANIFEST_NAME), Self::write_sqlite),
+        };
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        write(self, &tmp)
+            .and_then(|()| Ok(fs::rename(&tmp, &path)?))
+            .with_context(|| format!("failed to write manifest `{}`", path.display()))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+            })
+    }
+
+    /// Writes the entries as CSV with a header row, quoting fields as
+    /// RFC 4180 has it.
+    fn write_csv(&self, path: &Path) -> anyhow::Result<()> {
+        let field = |text: &str| {
+            if text.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            } else {
+                text.to_owned()
+            }
+        };
+
+        let mut csv = String::from("source,path,size,hash,tag,category,compressed\r\n");
+        for entry in &self.entries {
+            let row = [
+                field(&entry.source.to_string_lossy()),
+                field(&entry.path.to_string_lossy()),
+                entry.size.to_string(),
+                field(&entry.hash),
+                field(&entry.tag),
+                field(entry.category.as_deref().unwrap_or_default()),
+                entry.compressed.to_string(),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push_str("\r\n");
+        }
+        fs::write(path, csv)?;
+
+        Ok(())
+    }
+
+    /// Writes the entries into a fresh SQLite database, with the run's
+    /// settings in `meta` and the locked sources in `locked`.
+    fn write_sqlite(&self, path: &Path) -> anyhow::Result<()> {
+        // a leftover from an interrupted run would already have the tables
+        let _ = fs::remove_file(path);
+        let mut db = Connection::open(path)?;
+        let tx = db.transaction()?;
+        tx.execute_batch(
+            "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT);
+             CREATE TABLE entries (
+                 source TEXT NOT NULL,
+                 path TEXT NOT NULL,
+                 size INTEGER NOT NULL,
+                 hash TEXT NOT NULL,
+                 tag TEXT NOT NULL,
+                 category TEXT,
+                 compressed INTEGER NOT NULL
+             );
+             CREATE INDEX entries_path ON entries (path);
+             CREATE INDEX entries_size ON entries (size);
+             CREATE INDEX entries_category ON entries (category);
+             CREATE INDEX entries_hash ON entries (hash);
+             CREATE TABLE locked (source TEXT NOT NULL);",
+        )?;
+
+        let hash_algo = serde_json::to_value(self.hash_algo)?;
+        tx.execute(
+            "INSERT INTO meta VALUES ('hash_algo', ?1), ('relative_to', ?2)",
+            params![
+                hash_algo.as_str(),
+                self.relative_to
+                    .as_deref()
+                    .map(|root| root.to_string_lossy())
+            ],
+        )?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO entries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            for entry in &self.entries {
+                insert.execute(params![
+                    entry.source.to_string_lossy(),
+                    entry.path.to_string_lossy(),
+                    // SQLite integers are signed
+                    i64::try_from(entry.size)?,
+                    entry.hash,
+                    entry.tag,
+                    entry.category,
+                    entry.compressed,
+                ])?;
+            }
+            let mut insert = tx.prepare("INSERT INTO locked VALUES (?1)")?;
+            for source in &self.locked {
+                insert.execute(params![source.to_string_lossy()])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Re-hashes every copy below `dest` with the algorithm the manifest was
+    /// written with, printing each file that is missing or differs and
+    /// returning how many did. Where the system allows, copies are read
+    /// back from the disk even if they were just written.
+    pub fn verify(&self, dest: &Path) -> usize {
+        let mut failed = 0;
+
+        for entry in &self.entries {
+            let path = dest.join(&entry.path);
+            // a copy that can't be evicted can't be read either, which the
+            // hashing below reports
+            let _ = evict_cached(&path);
+            let hash = open_copy(&path, entry.compressed)
+                .and_then(|copy| hash_reader(copy, self.hash_algo));
+            match hash {
+                Ok(hash) if hash == entry.hash => {}
+                Ok(_) => {
+                    failed += 1;
+                    println!("{} # modified", path.display());
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    failed += 1;
+                    println!("{} # missing", path.display());
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("{} # unreadable ({})", path.display(), err);
+                }
+            }
+        }
+
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ScanOptions,
+        testing::{scan, serial, TempTree},
+    };
+
+    #[test]
+    fn verification_uses_the_recorded_algorithm() {
+        let _serial = serial();
+        for hash_algo in [HashAlgo::Md5, HashAlgo::Blake3] {
+            let tree = TempTree::new();
+            tree.file("src/.env", "abc");
+            let dest = tree.path().join("dest");
+            scan(&ScanOptions {
+                roots: vec![tree.path().join("src")],
+                dest: Some(dest.clone()),
+                hash_algo,
+                ..ScanOptions::default()
+            });
+
+            let manifest = Manifest::load(&dest).unwrap();
+            assert_eq!(manifest.hash_algo, hash_algo);
+            assert_eq!(
+                manifest.entries[0].hash,
+                hash_reader(&b"abc"[..], hash_algo).unwrap()
+            );
+            assert_eq!(manifest.verify(&dest), 0);
+
+            fs::write(dest.join(&manifest.entries[0].path), "abd").unwrap();
+            assert_eq!(manifest.verify(&dest), 1);
+        }
+    }
+
+    #[test]
+    fn relative_manifests_restore_below_another_base() {
+        let _serial = serial();
+        let tree = TempTree::new();
+        let root = tree.dir("home");
+        tree.file("home/docs/.env", "KEY=value\n");
+        let dest = tree.path().join("dest");
+        scan(&ScanOptions {
+            roots: vec![root.clone()],
+            dest: Some(dest.clone()),
+            relative_to: Some(root.clone()),
+            ..ScanOptions::default()
+        });
+
+        let manifest = Manifest::load(&dest).unwrap();
+        assert_eq!(manifest.relative_to.as_deref(), Some(&*root));
+        assert_eq!(manifest.entries[0].source, Path::new("docs").join(".env"));
+        assert_eq!(
+            manifest.source_path(&manifest.entries[0], None),
+            root.join("docs/.env")
+        );
+
+        // on another machine the same tree lives somewhere else
+        let moved = tree.path().join("elsewhere");
+        assert_eq!(manifest.restore(&dest, Some(&moved), false), 0);
+        assert_eq!(
+            fs::read_to_string(moved.join("docs/.env")).unwrap(),
+            "KEY=value\n"
+        );
+        // nothing is overwritten
+        assert_eq!(manifest.restore(&dest, None, false), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes_survive_a_backup_and_restore() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _serial = serial();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        for compress_level in [None, Some(6)] {
+            let tree = TempTree::new();
+            let root = tree.dir("home");
+            let script = tree.file("home/bin/.env", "#!/bin/sh\necho hi\n");
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o751)).unwrap();
+            let dest = tree.path().join("dest");
+            scan(&ScanOptions {
+                roots: vec![root.clone()],
+                dest: Some(dest.clone()),
+                relative_to: Some(root.clone()),
+                compress_level,
+                preserve_permissions: true,
+                ..ScanOptions::default()
+            });
+
+            let manifest = Manifest::load(&dest).unwrap();
+            let entry = &manifest.entries[0];
+            assert_eq!(entry.compressed, compress_level.is_some());
+            assert_eq!(entry.permissions.as_ref().map(|p| p.mode), Some(0o751));
+            assert_eq!(mode(&dest.join(&entry.path)), 0o751);
+
+            let moved = tree.path().join("elsewhere");
+            assert_eq!(manifest.restore(&dest, Some(&moved), true), 0);
+            assert_eq!(mode(&moved.join("bin/.env")), 0o751);
+        }
+    }
+
+    /// The fields of each record of RFC 4180 CSV, header included.
+    fn parse_csv(text: &str) -> Vec<Vec<String>> {
+        let (mut records, mut record, mut field) = (Vec::new(), Vec::new(), String::new());
+        let mut chars = text.chars().peekable();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (_, '"') => quoted = !quoted,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\r') => {}
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                _ => field.push(c),
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn entries_round_trip_through_every_format() {
+        let tree = TempTree::new();
+        let dest = tree.path().join("dest");
+        let mut manifest = Manifest::new(HashAlgo::Sha256, None);
+        for (source, size, category, compressed) in [
+            ("/home/me/.env", 10, Some("secrets"), false),
+            (
+                "/home/me/q1, \"final\"\nv2.csv",
+                2_000_000,
+                Some("spreadsheets"),
+                true,
+            ),
+            ("/home/me/custom.bin", 0, None, false),
+        ] {
+            manifest.entries.push(ManifestEntry {
+                source: PathBuf::from(source),
+                path: PathBuf::from(source.trim_start_matches('/')),
+                size,
+                hash: format!("{:064x}", size),
+                tag: "tag".to_owned(),
+                category: category.map(str::to_owned),
+                compressed,
+                permissions: None,
+            });
+        }
+        manifest.locked.push(PathBuf::from("/home/me/open.pst"));
+        let rows = |manifest: &Manifest| {
+            manifest
+                .entries
+                .iter()
+                .map(|entry| {
+                    vec![
+                        entry.source.to_string_lossy().into_owned(),
+                        entry.path.to_string_lossy().into_owned(),
+                        entry.size.to_string(),
+                        entry.hash.clone(),
+                        entry.tag.clone(),
+                        entry.category.clone().unwrap_or_default(),
+                        entry.compressed.to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = rows(&manifest);
+
+        for format in [
+            ManifestFormat::Json,
+            ManifestFormat::Csv,
+            ManifestFormat::Sqlite,
+        ] {
+            manifest.save(&dest, format).unwrap();
+            assert_eq!(rows(&Manifest::load(&dest).unwrap()), expected);
+        }
+        assert!(fs::read_dir(&dest).unwrap().all(|file| !file
+            .unwrap()
+            .path()
+            .to_string_lossy()
+            .ends_with(".tmp")));
+
+        let csv = parse_csv(&fs::read_to_string(dest.join(CSV_MANIFEST_NAME)).unwrap());
+        assert_eq!(
+            csv[0],
+            [
+                "source",
+                "path",
+                "size",
+                "hash",
+                "tag",
+                "category",
+                "compressed"
+            ]
+        );
+        assert_eq!(csv[1..], expected);
+
+        let db = Connection::open(dest.join(SQLITE_MANIFEST_NAME)).unwrap();
+        let mut query = db
+            .prepare(
+                "SELECT source, path, size, hash, tag, category, compressed FROM entries \
+                 ORDER BY rowid",
+            )
+            .unwrap();
+        let stored = query
+            .query_map([], |row| {
+                Ok(vec![
+                    row.get::<_, String>(0)?,
+                    row.get(1)?,
+                    row.get::<_, i64>(2)?.to_string(),
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                    row.get::<_, bool>(6)?.to_string(),
+                ])
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(stored, expected);
+        // what the indexes are there for
+        let large: i64 = db
+            .query_row(
+                "SELECT count(*) FROM entries WHERE category IS NOT NULL AND size > 1000000",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(large, 1);
+        let locked: String = db
+            .query_row("SELECT source FROM locked", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(locked, "/home/me/open.pst");
+        let algo: String = db
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'hash_algo'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(algo, "sha256");
+    }
+
+    fn manifest_of(hash_algo: HashAlgo, entries: &[(&str, u64, &str)]) -> Manifest {
+        let mut manifest = Manifest::new(hash_algo, None);
+        for (source, size, hash) in entries {
+            manifest.entries.push(ManifestEntry {
+                source: PathBuf::from(source),
+                path: PathBuf::from(source.trim_start_matches('/')),
+                size: *size,
+                hash: (*hash).to_owned(),
+                tag: "dotenv".to_owned(),
+                category: Some("secrets".to_owned()),
+                compressed: false,
+                permissions: None,
+            });
+        }
+        manifest
+    }
+
+    #[test]
+    fn drift_lists_added_removed_and_modified_files() {
+        let tree = TempTree::new();
+        let old = manifest_of(
+            HashAlgo::Sha256,
+            &[
+                ("/home/me/kept.env", 10, "aa"),
+                ("/home/me/edited.env", 10, "bb"),
+                ("/home/me/grown.env", 10, "cc"),
+                ("/home/me/gone.env", 40, "dd"),
+            ],
+        );
+        let new = manifest_of(
+            HashAlgo::Sha256,
+            &[
+                ("/home/me/kept.env", 10, "aa"),
+                ("/home/me/edited.env", 10, "b2"),
+                ("/home/me/grown.env", 25, "c2"),
+                ("/home/me/new.env", 5, "ee"),
+            ],
+        );
+        // one is read from its file, the other from its backup folder
+        old.save(&tree.path().join("old"), ManifestFormat::Json)
+            .unwrap();
+        new.save(&tree.path().join("new"), ManifestFormat::Json)
+            .unwrap();
+        let old = Manifest::read(&tree.path().join("old").join(MANIFEST_NAME)).unwrap();
+        let new = Manifest::read(&tree.path().join("new")).unwrap();
+
+        let drift = new.drift_from(&old);
+        let sources = |files: &[DriftedFile]| {
+            files
+                .iter()
+                .map(|file| (file.source.display().to_string(), file.size))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sources(&drift.added), [("/home/me/new.env".to_owned(), 5)]);
+        assert_eq!(
+            sources(&drift.removed),
+            [("/home/me/gone.env".to_owned(), 40)]
+        );
+        let modified = drift
+            .modified
+            .iter()
+            .map(|file| (file.source.to_str().unwrap(), file.old_size, file.new_size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            modified,
+            [
+                ("/home/me/edited.env", 10, 10),
+                ("/home/me/grown.env", 10, 25)
+            ]
+        );
+        assert_eq!(drift.unchanged, 1);
+        assert_eq!(drift.size_delta, 15 + 5 - 40);
+        assert!(!drift.by_size);
+
+        // hashes of different algorithms can't be compared
+        let rehashed = manifest_of(
+            HashAlgo::Blake3,
+            &[
+                ("/home/me/kept.env", 10, "xx"),
+                ("/home/me/edited.env", 10, "yy"),
+            ],
+        );
+        let drift = rehashed.drift_from(&old);
+        assert!(drift.by_size && drift.modified.is_empty());
+        assert_eq!(drift.unchanged, 2);
+        assert_eq!(drift.removed.len(), 2);
+    }
+}
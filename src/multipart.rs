@@ -0,0 +1,217 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+pub enum MultiPartFormat {
+    /// `name.part1.rar`, `name.part2.rar`, ...
+    Rar,
+    /// `name.7z.001`, `name.7z.002`, ...
+    SevenZip,
+}
+
+/// One volume of an archive split across several files.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MultiPartArchive {
+    pub format: MultiPartFormat,
+    /// 1-based index of this part within its set.
+    pub index: u32,
+}
+
+impl MultiPartArchive {
+    /// Recognizes the part naming schemes from a lowercase file name,
+    /// returning the name shared by all parts of the set along with the part.
+    pub fn parse(file_name: &str) -> Option<(&str, Self)> {
+        if let Some(stem) = file_name.strip_suffix(".rar") {
+            let (base, part) = stem.rsplit_once(".part")?;
+            let index = parse_index(part)?;
+            return Some((
+                base,
+                Self {
+                    format: MultiPartFormat::Rar,
+                    index,
+                },
+            ));
+        }
+
+        let (base, part) = file_name.rsplit_once(".7z.")?;
+        Some((
+            base,
+            Self {
+                format: MultiPartFormat::SevenZip,
+                index: parse_index(part)?,
+            },
+        ))
+    }
+}
+
+fn parse_index(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok().filter(|&index| index > 0)
+}
+
+#[derive(Default)]
+struct PartSet {
+    seen: BTreeSet<u32>,
+    captured: BTreeSet<u32>,
+}
+
+/// Collects the parts of split archives met during a scan so incomplete sets
+/// can be reported once the whole tree has been seen.
+#[derive(Default)]
+pub struct MultiPartSets {
+    sets: BTreeMap<(PathBuf, String, MultiPartFormat), PartSet>,
+}
+
+impl MultiPartSets {
+    /// Records a part found at `path`. `captured` is false if the part was
+    /// skipped by the filters and therefore won't be part of the results.
+    pub fn record(&mut self, path: &Path, captured: bool) {
+        let Some(file_name) = path.file_name() else {
+            return;
+        };
+        let file_name = file_name.to_string_lossy().to_ascii_lowercase();
+        let Some((base, part)) = MultiPartArchive::parse(&file_name) else {
+            return;
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let set = self
+            .sets
+            .entry((dir, base.to_owned(), part.format))
+            .or_default();
+        set.seen.insert(part.index);
+        if captured {
+            set.captured.insert(part.index);
+        }
+    }
+
+    /// Prints one line per set that was at least partially captured and
+    /// warns about sets with missing or skipped parts.
+    pub fn report(&self) {
+        for ((dir, base, format), set) in &self.sets {
+            let Some(&total) = set.seen.last() else {
+                continue;
+            };
+            if set.captured.is_empty() {
+                continue;
+            }
+
+            let (tag, pattern) = match format {
+                MultiPartFormat::Rar => ("rar", format!("{}.partN.rar", base)),
+                MultiPartFormat::SevenZip => ("7z", format!("{}.7z.NNN", base)),
+            };
+            let path = dir.join(&pattern);
+            println!("{} # {}({} parts)", path.display(), tag, total);
+
+            let missing = (1..=total).filter(|index| !set.seen.contains(index));
+            let skipped = set.seen.difference(&set.captured).copied();
+            if let Some(parts) = join_parts(missing) {
+                eprintln!(
+                    "warning: `{}` is incomplete, missing parts: {}",
+                    path.display(),
+                    parts
+                );
+            }
+            if let Some(parts) = join_parts(skipped) {
+                eprintln!(
+                    "warning: `{}` is only partially captured, filtered out parts: {}",
+                    path.display(),
+                    parts
+                );
+            }
+        }
+    }
+}
+
+fn join_parts(parts: impl Iterator<Item = u32>) -> Option<String> {
+    let mut joined = String::new();
+    for part in parts {
+        if !joined.is_empty() {
+            joined.push_str(", ");
+        }
+        let _ = write!(joined, "{}", part);
+    }
+    (!joined.is_empty()).then_some(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tag_of, TempTree};
+
+    fn parse(name: &str) -> Option<(&str, MultiPartFormat, u32)> {
+        MultiPartArchive::parse(name).map(|(base, part)| (base, part.format, part.index))
+    }
+
+    #[test]
+    fn rar_parts_are_numbered_before_the_extension() {
+        assert_eq!(
+            parse("photos.part1.rar"),
+            Some(("photos", MultiPartFormat::Rar, 1))
+        );
+        assert_eq!(
+            parse("photos.2024.part12.rar"),
+            Some(("photos.2024", MultiPartFormat::Rar, 12))
+        );
+        assert_eq!(parse("photos.rar"), None);
+        assert_eq!(parse("photos.part.rar"), None);
+        assert_eq!(parse("photos.part0.rar"), None);
+        assert_eq!(parse("photos.partx.rar"), None);
+    }
+
+    #[test]
+    fn seven_zip_parts_are_numbered_after_the_extension() {
+        assert_eq!(
+            parse("photos.7z.001"),
+            Some(("photos", MultiPartFormat::SevenZip, 1))
+        );
+        assert_eq!(
+            parse("photos.7z.010"),
+            Some(("photos", MultiPartFormat::SevenZip, 10))
+        );
+        assert_eq!(parse("photos.7z"), None);
+        assert_eq!(parse("photos.7z.000"), None);
+        assert_eq!(parse("photos.7z.bak"), None);
+    }
+
+    #[test]
+    fn parts_are_classified_with_their_index() {
+        let tree = TempTree::new();
+        assert_eq!(tag_of(&tree.file("a.part2.rar", "")), "rar(part 2)");
+        assert_eq!(tag_of(&tree.file("a.7z.003", "")), "7z(part 3)");
+    }
+
+    #[test]
+    fn parts_of_a_set_are_grouped_by_directory_and_name() {
+        let mut sets = MultiPartSets::default();
+        sets.record(Path::new("/a/photos.part1.rar"), true);
+        sets.record(Path::new("/a/photos.part3.rar"), false);
+        sets.record(Path::new("/a/PHOTOS.7z.001"), true);
+        sets.record(Path::new("/b/photos.part1.rar"), true);
+        sets.record(Path::new("/a/notes.txt"), true);
+
+        let rar = &sets.sets[&(
+            PathBuf::from("/a"),
+            "photos".to_owned(),
+            MultiPartFormat::Rar,
+        )];
+        assert_eq!(rar.seen, BTreeSet::from([1, 3]));
+        assert_eq!(rar.captured, BTreeSet::from([1]));
+        assert_eq!(sets.sets.len(), 3);
+    }
+
+    #[test]
+    fn missing_and_skipped_parts_are_listed() {
+        assert_eq!(join_parts([2, 4].into_iter()).as_deref(), Some("2, 4"));
+        assert_eq!(join_parts(std::iter::empty()), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+use walkdir::DirEntry;
+
+use crate::EntryClassification;
+
+/// Receives what a scan finds while it runs, for driving a UI or service
+/// instead of the terminal output. Every method does nothing by default, so
+/// an observer only implements what it is interested in.
+///
+/// Calls come in walk order from a single thread, the one reporting results,
+/// never from the workers classifying files.
+pub trait ScanObserver {
+    /// An entry that was classified as anything but regular.
+    fn on_entry(&mut self, classification: &EntryClassification, path: &Path) {
+        let _ = (classification, path);
+    }
+
+    /// An entry the walk couldn't read, which is left out of the scan.
+    fn on_error(&mut self, error: &walkdir::Error) {
+        let _ = error;
+    }
+
+    /// The number of files and their total size seen so far below the root,
+    /// including those left out by filters. Called after every file.
+    fn on_progress(&mut self, files: u64, bytes: u64) {
+        let _ = (files, bytes);
+    }
+
+    /// The walked entry itself, for observers that need more than its path.
+    /// An error stops the scan. Forwards to [`Self::on_entry`] by default,
+    /// skipping regular entries.
+    fn on_walked(
+        &mut self,
+        entry: DirEntry,
+        classification: EntryClassification,
+    ) -> anyhow::Result<()> {
+        if !classification.is_regular() {
+            self.on_entry(&classification, entry.path());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::{config::ScanOptions, scan_with_observer, testing::TempTree};
+
+    /// Writes down every call, with paths relative to the root.
+    #[derive(Default)]
+    struct Recorder {
+        root: PathBuf,
+        entries: Vec<(PathBuf, String)>,
+        errors: Vec<PathBuf>,
+        progress: Vec<(u64, u64)>,
+    }
+
+    impl ScanObserver for Recorder {
+        fn on_entry(&mut self, classification: &EntryClassification, path: &Path) {
+            let path = path.strip_prefix(&self.root).unwrap().to_path_buf();
+            self.entries.push((path, classification.to_string()));
+        }
+
+        fn on_error(&mut self, error: &walkdir::Error) {
+            let path = error.path().unwrap().strip_prefix(&self.root).unwrap();
+            self.errors.push(path.to_path_buf());
+        }
+
+        fn on_progress(&mut self, files: u64, bytes: u64) {
+            self.progress.push((files, bytes));
+        }
+    }
+
+    fn record(root: &Path, config: &ScanOptions) -> Recorder {
+        let mut recorder = Recorder {
+            root: root.to_path_buf(),
+            ..Recorder::default()
+        };
+        scan_with_observer(root, config, &mut recorder).unwrap();
+        recorder.entries.sort();
+        recorder
+    }
+
+    #[test]
+    fn observers_hear_of_every_classified_entry_and_file() {
+        let tree = TempTree::new();
+        tree.file("app/.env", "KEY=value\n");
+        tree.file("app/notes.txt", "0123456789");
+        tree.file(".ssh/id_ed25519", "key");
+        tree.dir("app/.git");
+
+        let recorder = record(tree.path(), &ScanOptions::default());
+        assert_eq!(
+            recorder.entries,
+            [
+                (
+                    PathBuf::from(".ssh/id_ed25519"),
+                    "secret(private-key)".to_owned()
+                ),
+                (PathBuf::from("app/.env"), "dotenv".to_owned()),
+                (PathBuf::from("app/.git"), "git".to_owned()),
+            ]
+        );
+        assert!(recorder.errors.is_empty());
+        // once per file, regular ones included, counting up to the total
+        assert_eq!(recorder.progress.len(), 3);
+        assert!(recorder.progress.is_sorted());
+        assert_eq!(recorder.progress.last(), Some(&(3, 23)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn observers_hear_of_entries_the_walk_cannot_read() {
+        let tree = TempTree::new();
+        tree.file(".env", "KEY=value\n");
+        std::os::unix::fs::symlink("missing", tree.path().join("dangling")).unwrap();
+
+        let recorder = record(
+            tree.path(),
+            &ScanOptions {
+                follow_links: true,
+                ..ScanOptions::default()
+            },
+        );
+        assert_eq!(recorder.errors, [PathBuf::from("dangling")]);
+        assert_eq!(recorder.entries.len(), 1);
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    io::Read,
+    iter,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Most of a file read ahead, enough for classifying it and for copying
+/// small files, without pushing everything else out of the OS cache for a
+/// large one that is far from being copied.
+const READ_AHEAD_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Threads reading ahead, however many files are.
+const READ_AHEAD_THREADS: usize = 4;
+
+/// Runs `read` on a thread of its own and waits at most `timeout` for it,
+/// so a file on a hung network share or failing disk can't stall the scan.
+/// Yields nothing if it took longer. Such a thread can't be stopped, it is
+/// left to finish or stay blocked in the background, and its result is
+/// dropped. Without a timeout `read` simply runs on the calling thread.
+pub fn with_timeout<R: Send + 'static>(
+    timeout: Option<Duration>,
+    read: impl FnOnce() -> R + Send + 'static,
+) -> Option<R> {
+    let Some(timeout) = timeout else {
+        return Some(read());
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(read());
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+/// Passes `items` through unchanged while reading the files of the next
+/// `depth` ones on a few threads of their own, so they are in the OS cache
+/// by the time they are needed. `path` picks the file to read for an item,
+/// if any. Reads that can't keep up are dropped rather than holding up the
+/// items, and the threads stop once the iterator is dropped.
+pub fn read_ahead<T>(
+    items: impl Iterator<Item = T>,
+    depth: usize,
+    path: impl Fn(&T) -> Option<PathBuf>,
+) -> impl Iterator<Item = T> {
+    let depth = depth.max(1);
+    let (path_tx, path_rx) = mpsc::sync_channel::<PathBuf>(depth);
+    let path_rx = Arc::new(Mutex::new(path_rx));
+    for _ in 0..depth.min(READ_AHEAD_THREADS) {
+        let path_rx = Arc::clone(&path_rx);
+        thread::spawn(move || {
+            let mut buf = vec![0; 256 * 1024];
+            loop {
+                let next = path_rx.lock().unwrap_or_else(|err| err.into_inner()).recv();
+                let Ok(path) = next else {
+                    break;
+                };
+                // only the reading matters, what was read is thrown away
+                if let Ok(file) = File::open(&path) {
+                    let mut file = file.take(READ_AHEAD_BYTES);
+                    while file.read(&mut buf).is_ok_and(|read| read > 0) {}
+                }
+            }
+        });
+    }
+
+    let mut items = items.fuse();
+    let mut ahead = VecDeque::with_capacity(depth);
+    iter::from_fn(move || {
+        while ahead.len() < depth {
+            let Some(item) = items.next() else {
+                break;
+            };
+            if let Some(path) = path(&item) {
+                let _ = path_tx.try_send(path);
+            }
+            ahead.push_back(item);
+        }
+        ahead.pop_front()
+    })
+}
+
+/// Runs `f` over `items` on at most `limit` threads at a time, each taking
+/// the next item once it is done with one. With a limit of one or a single
+/// item everything runs on the calling thread, in order.
+pub fn for_each_limited<T: Send>(
+    items: impl IntoIterator<Item = T, IntoIter: Send>,
+    limit: usize,
+    f: impl Fn(T) + Sync,
+) {
+    let items = items.into_iter();
+    let workers = limit.min(items.size_hint().1.unwrap_or(usize::MAX));
+    if workers <= 1 {
+        items.for_each(f);
+        return;
+    }
+
+    let queue = Mutex::new(items);
+    let next = || queue.lock().unwrap_or_else(|err| err.into_inner()).next();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(item) = next() {
+                    f(item);
+                }
+            });
+        }
+    });
+}
+
+/// Runs `map` over `items` on `jobs` worker threads and feeds the results
+/// to `sink` in the order the items came in, as if everything ran serially.
+///
+/// `items` is drained on the calling thread and `sink` runs on a thread of
+/// its own. At most `window` items are in flight at once, which bounds both
+/// the channels and the buffer that puts results back in order. The first
+/// error from `sink` stops taking new items and is returned.
+pub fn ordered_map<T, R, E>(
+    items: impl Iterator<Item = T>,
+    jobs: usize,
+    window: usize,
+    map: impl Fn(T) -> R + Sync,
+    mut sink: impl FnMut(R) -> Result<(), E> + Send,
+) -> Result<(), E>
+where
+    T: Send,
+    R: Send,
+    E: Send,
+{
+    if jobs <= 1 {
+        for item in items {
+            sink(map(item))?;
+        }
+        return Ok(());
+    }
+
+    let window = window.max(1);
+    let (work_tx, work_rx) = mpsc::sync_channel::<(usize, T)>(window);
+    let (result_tx, result_rx) = mpsc::sync_channel::<(usize, R)>(window);
+    // one permit per item in flight, handed back by the sink once it is
+    // done with an item, so a slow item can't make the others pile up
+    let (permit_tx, permit_rx) = mpsc::sync_channel::<()>(window);
+    for _ in 0..window {
+        let _ = permit_tx.send(());
+    }
+
+    let work_rx = Mutex::new(work_rx);
+    let map = &map;
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let result_tx = result_tx.clone();
+            let work_rx = &work_rx;
+            scope.spawn(move || loop {
+                let next = work_rx.lock().unwrap_or_else(|err| err.into_inner()).recv();
+                let Ok((seq, item)) = next else {
+                    break;
+                };
+                // keep draining after the sink gave up so the producer
+                // never blocks on a full queue
+                let _ = result_tx.send((seq, map(item)));
+            });
+        }
+        drop(result_tx);
+
+        let printer = scope.spawn(move || {
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (seq, result) in result_rx {
+                pending.insert(seq, result);
+                while let Some(result) = pending.remove(&next) {
+                    sink(result)?;
+                    next += 1;
+                    let _ = permit_tx.send(());
+                }
+            }
+            Ok(())
+        });
+
+        for (seq, item) in items.enumerate() {
+            // the sink dropping its permit sender means it stopped
+            if permit_rx.recv().is_err() || work_tx.send((seq, item)).is_err() {
+                break;
+            }
+        }
+        drop(work_tx);
+
+        printer.join().expect("printer thread panicked")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Sleeps longer for earlier items, so workers finish out of order.
+    fn slow_square(item: u64) -> u64 {
+        thread::sleep(Duration::from_millis(20 - item % 20));
+        item * item
+    }
+
+    #[test]
+    fn results_come_out_in_input_order() {
+        let collect = |jobs| {
+            let mut results = Vec::new();
+            ordered_map(0..40, jobs, 8, slow_square, |result| {
+                results.push(result);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+            results
+        };
+        let serial = collect(1);
+        assert_eq!(serial, (0..40).map(|item| item * item).collect::<Vec<_>>());
+        assert_eq!(collect(4), serial);
+    }
+
+    #[test]
+    fn no_more_than_the_window_is_in_flight() {
+        let (in_flight, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        ordered_map(
+            0..50,
+            8,
+            3,
+            |item| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                slow_square(item)
+            },
+            |_| {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, ()>(())
+            },
+        )
+        .unwrap();
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn sink_errors_stop_taking_items() {
+        let taken = AtomicUsize::new(0);
+        let result = ordered_map(
+            0..1000,
+            4,
+            4,
+            |item: u64| {
+                taken.fetch_add(1, Ordering::SeqCst);
+                item
+            },
+            |item| if item == 5 { Err(item) } else { Ok(()) },
+        );
+        assert_eq!(result, Err(5));
+        assert!(taken.load(Ordering::SeqCst) < 1000);
+    }
+
+    #[test]
+    fn no_more_than_the_limit_runs_at_once() {
+        for limit in [1, 2, 3] {
+            let (running, peak, done) = (
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            );
+            for_each_limited(0..12, limit, |item: u64| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                slow_square(item);
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+            assert_eq!(done.load(Ordering::SeqCst), 12);
+            assert!(peak.load(Ordering::SeqCst) <= limit);
+        }
+    }
+
+    #[test]
+    fn reads_past_the_timeout_yield_nothing() {
+        assert_eq!(with_timeout(None, || 1), Some(1));
+        assert_eq!(with_timeout(Some(Duration::from_secs(10)), || 2), Some(2));
+        let slow = with_timeout(Some(Duration::from_millis(10)), || {
+            thread::sleep(Duration::from_millis(500));
+        });
+        assert_eq!(slow, None);
+    }
+
+    #[test]
+    fn read_ahead_passes_items_through() {
+        let items = read_ahead(0..10, 3, |_| None).collect::<Vec<_>>();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+
+    /// A reader that hangs like a file on a stalled share, until released
+    /// by dropping the other end of `release`.
+    struct HungReader {
+        release: mpsc::Receiver<()>,
+    }
+
+    impl Read for HungReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            let _ = self.release.recv();
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn reads_that_hang_time_out_and_are_left_behind() {
+        let (release, blocked) = mpsc::channel();
+        let (done_tx, done) = mpsc::channel();
+        let mut reader = HungReader { release: blocked };
+        let start = std::time::Instant::now();
+        let result = with_timeout(Some(Duration::from_millis(50)), move || {
+            let mut contents = Vec::new();
+            let read = reader.read_to_end(&mut contents).map(|_| contents);
+            let _ = done_tx.send(());
+            read.is_ok()
+        });
+        assert_eq!(result, None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the read is still going, and finishes on its own once unblocked
+        assert!(done.try_recv().is_err());
+        drop(release);
+        done.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn read_ahead_keeps_the_order_and_stays_depth_items_ahead() {
+        let pulled = AtomicUsize::new(0);
+        let picked = Mutex::new(Vec::new());
+        let items = (0..10).inspect(|_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut ahead = read_ahead(items, 3, |item| {
+            picked.lock().unwrap().push(*item);
+            // odd items have nothing to read
+            (item % 2 == 0).then(|| PathBuf::from(format!("/nonexistent/{}", item)))
+        });
+
+        assert_eq!(pulled.load(Ordering::SeqCst), 0);
+        assert_eq!(ahead.next(), Some(0));
+        assert_eq!(pulled.load(Ordering::SeqCst), 3);
+        assert_eq!(ahead.next(), Some(1));
+        assert_eq!(pulled.load(Ordering::SeqCst), 4);
+        assert_eq!(ahead.collect::<Vec<_>>(), (2..10).collect::<Vec<_>>());
+        assert_eq!(*picked.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn upcoming_files_are_opened_before_they_are_reached() {
+        use crate::testing::{opened_files, TempTree};
+
+        let tree = TempTree::new();
+        let paths = (0..8)
+            .map(|i| tree.file(&format!("{}.bin", i), vec![0; 4096]))
+            .collect::<Vec<_>>();
+        let opened = opened_files(tree.path(), || {
+            let mut ahead = read_ahead(paths.iter(), 3, |path| Some(path.to_path_buf()));
+            assert_eq!(ahead.next(), Some(&paths[0]));
+            // the reads happen on their own threads
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        assert_eq!(opened, ["0.bin", "1.bin", "2.bin"]);
+    }
+}
@@ -0,0 +1,1432 @@
+use anyhow::{bail, Context};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use walkdir::DirEntry;
+
+pub struct Platform {
+    pub fs_dir_sep: char,
+    pub sys_dir: String,
+    // each of these is only read on some platforms
+    #[allow(dead_code)]
+    pub user_dir: String,
+    #[allow(dead_code)]
+    pub app_data: String,
+    pub tmp_dir: String,
+}
+
+#[cfg(windows)]
+#[link(name = "secur32")]
+extern "system" {
+    fn GetUserNameW(buf: *mut u16, len: *mut u32) -> u32;
+}
+
+#[cfg(windows)]
+pub static PLATFORM: Lazy<Platform> = Lazy::new(|| unsafe {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    let mut buf = [0u16; 64];
+    #[allow(clippy::cast_possible_truncation)]
+    let mut len: u32 = buf.len() as u32;
+
+    if GetUserNameW(buf.as_mut_ptr(), &mut len) == 0 {
+        panic!("failed to get user name");
+    }
+
+    // ensure string is terminated
+    buf[buf.len() - 1] = u16::default();
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+
+    let name = OsString::from_wide(&buf[..end])
+        .as_os_str()
+        .to_string_lossy()
+        .into_owned();
+
+    Platform {
+        fs_dir_sep: '\\',
+        sys_dir: "C:\\Windows".into(),
+        user_dir: format!("C:\\users\\{}", name),
+        app_data: format!("C:\\users\\{}\\appdata", name),
+        tmp_dir: format!("C:\\users\\{}\\appdata\\local\\temp", name),
+    }
+});
+
+#[cfg(unix)]
+pub static PLATFORM: Lazy<Platform> = Lazy::new(|| {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/root".into());
+
+    Platform {
+        fs_dir_sep: '/',
+        sys_dir: "/proc".into(),
+        app_data: format!("{}/.local/share", home),
+        user_dir: home,
+        tmp_dir: "/tmp".into(),
+    }
+});
+
+/// Roots scanned when none are given: every drive letter on Windows, the
+/// filesystem root elsewhere.
+#[cfg(windows)]
+pub fn scan_roots() -> Vec<PathBuf> {
+    ('A'..='Z')
+        .map(|letter| PathBuf::from(format!("{}:{}", letter, PLATFORM.fs_dir_sep)))
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn scan_roots() -> Vec<PathBuf> {
+    vec![PathBuf::from(PLATFORM.fs_dir_sep.to_string())]
+}
+
+/// Identifies the filesystem a path lives on: `st_dev` on Unix, the volume
+/// serial number on Windows.
+pub type DeviceId = u64;
+
+#[cfg(unix)]
+pub fn device_id(path: &Path) -> io::Result<DeviceId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(std::fs::metadata(path)?.dev())
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetVolumeInformationByHandleW(
+        file: std::os::windows::io::RawHandle,
+        volume_name: *mut u16,
+        volume_name_len: u32,
+        serial_number: *mut u32,
+        max_component_len: *mut u32,
+        fs_flags: *mut u32,
+        fs_name: *mut u16,
+        fs_name_len: u32,
+    ) -> u32;
+}
+
+#[cfg(windows)]
+pub fn device_id(path: &Path) -> io::Result<DeviceId> {
+    use std::{
+        fs::OpenOptions,
+        os::windows::{fs::OpenOptionsExt, io::AsRawHandle},
+        ptr,
+    };
+
+    // required to open a handle to a directory
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+
+    let mut serial = 0u32;
+    let ok = unsafe {
+        GetVolumeInformationByHandleW(
+            file.as_raw_handle(),
+            ptr::null_mut(),
+            0,
+            &mut serial,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            0,
+        )
+    };
+
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(DeviceId::from(serial))
+}
+
+/// Whether `entry` is on the filesystem identified by `root_dev`, like
+/// `find -xdev`. Only directories can lead onto another filesystem, so files
+/// are always accepted, as are directories whose device can't be determined.
+pub fn same_filesystem(root_dev: DeviceId, entry: &DirEntry) -> bool {
+    if !entry.file_type().is_dir() {
+        return true;
+    }
+
+    device_id(entry.path()).map_or(true, |dev| dev == root_dev)
+}
+
+/// Reparse tag of NTFS junctions, which are reported as symlinks but usually
+/// redirect into system locations such as `Documents and Settings`.
+/// Mounted volumes share it.
+pub const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+/// Symbolic links created by `mklink`.
+pub const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+/// Files optimized by Data Deduplication. Their data is real, it is only
+/// stored in a shared chunk store.
+pub const IO_REPARSE_TAG_DEDUP: u32 = 0x8000_0013;
+/// Files moved to tape or other slow storage by hierarchical storage
+/// management, which reading recalls.
+pub const IO_REPARSE_TAG_HSM: u32 = 0xC000_0004;
+pub const IO_REPARSE_TAG_HSM2: u32 = 0x8000_0006;
+/// OneDrive and other cloud files. A placeholder whose data isn't on the
+/// disk is downloaded when read. The 16 variants differ in bits 12 to 15.
+pub const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001A;
+/// App execution aliases, the `python.exe` and such in `WindowsApps`.
+pub const IO_REPARSE_TAG_APPEXECLINK: u32 = 0x8000_001B;
+/// Files compressed by the Windows Overlay Filter, as with `compact /exe`.
+pub const IO_REPARSE_TAG_WOF: u32 = 0x8000_0017;
+/// Windows Container Isolation files, backed by a container image layer.
+pub const IO_REPARSE_TAG_WCI: u32 = 0x8000_0018;
+/// Symbolic links created inside WSL.
+pub const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xA000_001D;
+
+/// Names `--follow-reparse` accepts for the common reparse tags.
+pub const REPARSE_TAGS: &[(&str, u32)] = &[
+    ("mount-point", IO_REPARSE_TAG_MOUNT_POINT),
+    ("symlink", IO_REPARSE_TAG_SYMLINK),
+    ("dedup", IO_REPARSE_TAG_DEDUP),
+    ("hsm", IO_REPARSE_TAG_HSM),
+    ("hsm2", IO_REPARSE_TAG_HSM2),
+    ("cloud", IO_REPARSE_TAG_CLOUD),
+    ("appexeclink", IO_REPARSE_TAG_APPEXECLINK),
+    ("wof", IO_REPARSE_TAG_WOF),
+    ("wci", IO_REPARSE_TAG_WCI),
+    ("lx-symlink", IO_REPARSE_TAG_LX_SYMLINK),
+];
+
+/// Parses a reparse tag for `--follow-reparse`: one of the names in
+/// [`REPARSE_TAGS`], or any tag as a hexadecimal number like `0x80000013`.
+pub fn parse_reparse_tag(text: &str) -> anyhow::Result<u32> {
+    let text = text.trim();
+    if let Some((_, tag)) = REPARSE_TAGS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(text))
+    {
+        return Ok(*tag);
+    }
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16)
+            .map(normalize_reparse_tag)
+            .with_context(|| format!("invalid reparse tag `{}`", text)),
+        None => bail!(
+            "unknown reparse tag `{}`, expected a number like `0x80000013` or one of: {}",
+            text,
+            REPARSE_TAGS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Folds the variants of the cloud tag into [`IO_REPARSE_TAG_CLOUD`], so
+/// `cloud` covers them all.
+fn normalize_reparse_tag(tag: u32) -> u32 {
+    const CLOUD_VARIANT_BITS: u32 = 0x0000_F000;
+
+    if tag & !CLOUD_VARIANT_BITS == IO_REPARSE_TAG_CLOUD {
+        IO_REPARSE_TAG_CLOUD
+    } else {
+        tag
+    }
+}
+
+/// The reparse tag of a walked entry, with cloud variants folded together,
+/// or `None` if it is no reparse point. Only links are checked with a call
+/// of their own, for anything else the attributes from the directory
+/// listing tell.
+#[cfg(windows)]
+pub fn entry_reparse_tag(entry: &DirEntry) -> Option<u32> {
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+
+    if !entry.path_is_symlink() && !has_attribute(entry, FILE_ATTRIBUTE_REPARSE_POINT) {
+        return None;
+    }
+    reparse_tag(entry.path())
+        .ok()
+        .flatten()
+        .map(normalize_reparse_tag)
+}
+
+#[cfg(unix)]
+pub fn entry_reparse_tag(_entry: &DirEntry) -> Option<u32> {
+    None
+}
+
+#[cfg(windows)]
+#[repr(C)]
+struct Win32FindDataW {
+    file_attributes: u32,
+    creation_time: [u32; 2],
+    last_access_time: [u32; 2],
+    last_write_time: [u32; 2],
+    file_size_high: u32,
+    file_size_low: u32,
+    reserved0: u32,
+    reserved1: u32,
+    file_name: [u16; 260],
+    alternate_file_name: [u16; 14],
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn FindFirstFileW(
+        file_name: *const u16,
+        find_data: *mut Win32FindDataW,
+    ) -> std::os::windows::io::RawHandle;
+    fn FindClose(find_file: std::os::windows::io::RawHandle) -> u32;
+}
+
+#[cfg(windows)]
+fn to_wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
+
+/// Reads the reparse tag of `path` without following it, or `None` if it is
+/// not a reparse point.
+#[cfg(windows)]
+pub fn reparse_tag(path: &Path) -> io::Result<Option<u32>> {
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+
+    let name = to_wide(path);
+    let mut data = std::mem::MaybeUninit::<Win32FindDataW>::uninit();
+    let data = unsafe {
+        let handle = FindFirstFileW(name.as_ptr(), data.as_mut_ptr());
+        if handle as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        FindClose(handle);
+        data.assume_init()
+    };
+
+    // the tag is only meaningful in `reserved0` for reparse points
+    Ok((data.file_attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0).then_some(data.reserved0))
+}
+
+#[cfg(windows)]
+pub fn is_junction(path: &Path) -> bool {
+    matches!(reparse_tag(path), Ok(Some(IO_REPARSE_TAG_MOUNT_POINT)))
+}
+
+#[cfg(unix)]
+pub fn is_junction(_path: &Path) -> bool {
+    false
+}
+
+/// Directory for state kept between runs, such as the classification cache.
+#[cfg(windows)]
+pub fn state_dir() -> PathBuf {
+    Path::new(&PLATFORM.app_data).join("local").join("backup")
+}
+
+#[cfg(unix)]
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(&PLATFORM.user_dir).join(".cache"))
+        .join("backup")
+}
+
+/// Directory holding the user's settings: `%APPDATA%\backup` on Windows,
+/// `$XDG_CONFIG_HOME/backup` or `~/.config/backup` elsewhere.
+#[cfg(windows)]
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(&PLATFORM.app_data).join("roaming"))
+        .join("backup")
+}
+
+#[cfg(unix)]
+pub fn config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(&PLATFORM.user_dir).join(".config"))
+        .join("backup")
+}
+
+/// Bytes available to the current user on the volume holding `path`. If
+/// `path` doesn't exist yet, its closest existing ancestor is asked.
+pub fn free_space(path: &Path) -> anyhow::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .with_context(|| format!("no part of `{}` exists", path.display()))?;
+
+    volume_free_space(existing)
+        .with_context(|| format!("failed to query free space of `{}`", existing.display()))
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetDiskFreeSpaceExW(
+        directory: *const u16,
+        free_bytes_available: *mut u64,
+        total_bytes: *mut u64,
+        total_free_bytes: *mut u64,
+    ) -> u32;
+}
+
+#[cfg(windows)]
+fn volume_free_space(path: &Path) -> io::Result<u64> {
+    let name = to_wide(path);
+    let mut available = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            name.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(available)
+}
+
+#[cfg(unix)]
+fn volume_free_space(path: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let name = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(name.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+#[cfg(windows)]
+fn has_attribute(entry: &DirEntry, attribute: u32) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    entry
+        .metadata()
+        .is_ok_and(|metadata| metadata.file_attributes() & attribute != 0)
+}
+
+/// What a directory can hold besides files, directories and links. None
+/// of them have contents that could be copied.
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[cfg_attr(windows, allow(dead_code))]
+pub enum SpecialFileType {
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+}
+
+/// Whether the entry is a socket, a FIFO or a device node.
+#[cfg(unix)]
+pub fn special_file_type(entry: &DirEntry) -> Option<SpecialFileType> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = entry.file_type();
+    if file_type.is_socket() {
+        Some(SpecialFileType::Socket)
+    } else if file_type.is_fifo() {
+        Some(SpecialFileType::Fifo)
+    } else if file_type.is_block_device() {
+        Some(SpecialFileType::BlockDevice)
+    } else if file_type.is_char_device() {
+        Some(SpecialFileType::CharDevice)
+    } else {
+        None
+    }
+}
+
+/// Windows keeps its pipes and devices out of the filesystem.
+#[cfg(windows)]
+pub fn special_file_type(_entry: &DirEntry) -> Option<SpecialFileType> {
+    None
+}
+
+/// Whether the entry is hidden: the hidden attribute on Windows, a leading
+/// dot elsewhere.
+#[cfg(windows)]
+pub fn is_hidden(entry: &DirEntry) -> bool {
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    has_attribute(entry, FILE_ATTRIBUTE_HIDDEN)
+}
+
+#[cfg(unix)]
+pub fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Whether the entry carries the Windows system attribute. Other platforms
+/// have no equivalent.
+#[cfg(windows)]
+pub fn is_system(entry: &DirEntry) -> bool {
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+
+    has_attribute(entry, FILE_ATTRIBUTE_SYSTEM)
+}
+
+#[cfg(unix)]
+pub fn is_system(_entry: &DirEntry) -> bool {
+    false
+}
+
+/// Whether opening a file failed because another process holds it open
+/// without sharing, like a running database or a loaded registry hive.
+///
+/// Reading those would need a Volume Shadow Copy snapshot, which isn't
+/// supported yet.
+#[cfg(windows)]
+pub fn is_locked(err: &io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    matches!(
+        err.raw_os_error(),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+    )
+}
+
+/// Unix locks are advisory and never keep a reader out.
+#[cfg(unix)]
+pub fn is_locked(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(windows)]
+#[repr(C)]
+struct Win32FindStreamData {
+    stream_size: i64,
+    stream_name: [u16; 260 + 36],
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn FindFirstStreamW(
+        file_name: *const u16,
+        info_level: u32,
+        find_stream_data: *mut Win32FindStreamData,
+        flags: u32,
+    ) -> std::os::windows::io::RawHandle;
+    fn FindNextStreamW(
+        find_stream: std::os::windows::io::RawHandle,
+        find_stream_data: *mut Win32FindStreamData,
+    ) -> u32;
+}
+
+/// Names and sizes of the NTFS alternate data streams of `path`, such as
+/// the `Zone.Identifier` Windows attaches to downloads. The unnamed default
+/// stream holding the file's contents is left out, as is everything on
+/// filesystems without streams.
+#[cfg(windows)]
+pub fn list_ads(path: &Path) -> Vec<(String, u64)> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    const FIND_STREAM_INFO_STANDARD: u32 = 0;
+
+    fn named_stream(data: &Win32FindStreamData) -> Option<(String, u64)> {
+        let end = data
+            .stream_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(data.stream_name.len());
+        let name = OsString::from_wide(&data.stream_name[..end])
+            .to_string_lossy()
+            .into_owned();
+
+        // names come as `:name:$DATA`, the default stream as `::$DATA`
+        let name = name.strip_prefix(':')?.strip_suffix(":$DATA")?;
+        if name.is_empty() {
+            return None;
+        }
+        Some((
+            name.to_owned(),
+            u64::try_from(data.stream_size).unwrap_or(0),
+        ))
+    }
+
+    let name = to_wide(path);
+    let mut data = std::mem::MaybeUninit::<Win32FindStreamData>::uninit();
+    let mut streams = Vec::new();
+    unsafe {
+        let handle = FindFirstStreamW(
+            name.as_ptr(),
+            FIND_STREAM_INFO_STANDARD,
+            data.as_mut_ptr(),
+            0,
+        );
+        if handle as isize == -1 {
+            return streams;
+        }
+        loop {
+            streams.extend(named_stream(data.assume_init_ref()));
+            if FindNextStreamW(handle, data.as_mut_ptr()) == 0 {
+                break;
+            }
+        }
+        FindClose(handle);
+    }
+
+    streams
+}
+
+#[cfg(unix)]
+pub fn list_ads(_path: &Path) -> Vec<(String, u64)> {
+    Vec::new()
+}
+
+/// The path that opens the alternate data stream `name` of `path`.
+pub fn stream_path(path: &Path, name: &str) -> PathBuf {
+    let mut stream = path.as_os_str().to_os_string();
+    stream.push(":");
+    stream.push(name);
+    PathBuf::from(stream)
+}
+
+/// Identifies the physical disk a path is stored on, so that partitions of
+/// one disk can be told apart from separate disks.
+pub type DiskId = String;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetVolumePathNameW(file_name: *const u16, volume_path: *mut u16, len: u32) -> u32;
+    fn DeviceIoControl(
+        device: std::os::windows::io::RawHandle,
+        io_control_code: u32,
+        in_buffer: *const std::ffi::c_void,
+        in_buffer_size: u32,
+        out_buffer: *mut std::ffi::c_void,
+        out_buffer_size: u32,
+        bytes_returned: *mut u32,
+        overlapped: *mut std::ffi::c_void,
+    ) -> u32;
+}
+
+/// The root of the volume holding `path`, e.g. `C:\` or a mount folder.
+#[cfg(windows)]
+fn volume_root(path: &Path) -> Option<String> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    let name = to_wide(path);
+    let mut volume = [0u16; 260];
+    #[allow(clippy::cast_possible_truncation)]
+    if unsafe { GetVolumePathNameW(name.as_ptr(), volume.as_mut_ptr(), volume.len() as u32) } == 0 {
+        return None;
+    }
+    let end = volume.iter().position(|&c| c == 0).unwrap_or(volume.len());
+    Some(
+        OsString::from_wide(&volume[..end])
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// The disk holding `path`, from `IOCTL_STORAGE_GET_DEVICE_NUMBER` on the
+/// volume, or `None` if it can't be told, e.g. for network shares.
+#[cfg(windows)]
+pub fn physical_disk(path: &Path) -> Option<DiskId> {
+    use std::{
+        fs::OpenOptions,
+        os::windows::{fs::OpenOptionsExt, io::AsRawHandle},
+        ptr,
+    };
+
+    const IOCTL_STORAGE_GET_DEVICE_NUMBER: u32 = 0x002D_1080;
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct StorageDeviceNumber {
+        device_type: u32,
+        device_number: u32,
+        partition_number: u32,
+    }
+
+    let volume = volume_root(path)?;
+
+    // `C:\` is opened as the device `\\.\C:`, which needs no access rights
+    // to be queried
+    let device = format!(r"\\.\{}", volume.trim_end_matches('\\'));
+    let device = OpenOptions::new()
+        .access_mode(0)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .open(device)
+        .ok()?;
+
+    let mut number = StorageDeviceNumber::default();
+    let mut returned = 0u32;
+    #[allow(clippy::cast_possible_truncation)]
+    let ok = unsafe {
+        DeviceIoControl(
+            device.as_raw_handle(),
+            IOCTL_STORAGE_GET_DEVICE_NUMBER,
+            ptr::null(),
+            0,
+            ptr::addr_of_mut!(number).cast(),
+            std::mem::size_of::<StorageDeviceNumber>() as u32,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+
+    Some(format!("{}:{}", number.device_type, number.device_number))
+}
+
+/// The disk holding `path`, found by following its block device in sysfs
+/// up from the partition, or `None` for filesystems without one.
+#[cfg(target_os = "linux")]
+pub fn physical_disk(path: &Path) -> Option<DiskId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dev = std::fs::metadata(path).ok()?.dev();
+    let block = Path::new("/sys/dev/block")
+        .join(format!("{}:{}", libc::major(dev), libc::minor(dev)))
+        .canonicalize()
+        .ok()?;
+
+    // partitions are listed inside the directory of their disk
+    let disk = if block.join("partition").exists() {
+        block.parent()?
+    } else {
+        &block
+    };
+    Some(disk.file_name()?.to_string_lossy().into_owned())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn physical_disk(_path: &Path) -> Option<DiskId> {
+    None
+}
+
+/// What identifies a volume to its user, shown with `--drive-label` since
+/// drive letters and mount points can change between runs.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct VolumeInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The filesystem type, e.g. `NTFS` or `ext4`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<String>,
+}
+
+impl fmt::Display for VolumeInfo {
+    /// `"Backup" (NTFS)`, leaving out what isn't known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.label, &self.filesystem) {
+            (Some(label), Some(filesystem)) => write!(f, "\"{}\" ({})", label, filesystem),
+            (Some(label), None) => write!(f, "\"{}\"", label),
+            (None, Some(filesystem)) => write!(f, "({})", filesystem),
+            (None, None) => write!(f, "(unknown)"),
+        }
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetVolumeInformationW(
+        root_path: *const u16,
+        volume_name: *mut u16,
+        volume_name_len: u32,
+        serial_number: *mut u32,
+        max_component_len: *mut u32,
+        fs_flags: *mut u32,
+        fs_name: *mut u16,
+        fs_name_len: u32,
+    ) -> u32;
+}
+
+/// The label and filesystem of the volume holding `path`.
+#[cfg(windows)]
+pub fn volume_info(path: &Path) -> VolumeInfo {
+    use std::ptr;
+
+    let Some(root) = volume_root(path) else {
+        return VolumeInfo::default();
+    };
+    let root = to_wide(Path::new(&root));
+    let mut label = [0u16; 261];
+    let mut filesystem = [0u16; 261];
+    #[allow(clippy::cast_possible_truncation)]
+    let ok = unsafe {
+        GetVolumeInformationW(
+            root.as_ptr(),
+            label.as_mut_ptr(),
+            label.len() as u32,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            filesystem.as_mut_ptr(),
+            filesystem.len() as u32,
+        )
+    };
+    if ok == 0 {
+        return VolumeInfo::default();
+    }
+
+    let text = |buf: &[u16]| {
+        let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        Some(String::from_utf16_lossy(&buf[..end])).filter(|text| !text.is_empty())
+    };
+    VolumeInfo {
+        label: text(&label),
+        filesystem: text(&filesystem),
+    }
+}
+
+/// The label and filesystem of the volume holding `path`: the filesystem
+/// from the mount table, the label from udev's `/dev/disk/by-label` links
+/// to the mounted device.
+#[cfg(target_os = "linux")]
+pub fn volume_info(path: &Path) -> VolumeInfo {
+    let Ok(path) = std::fs::canonicalize(path) else {
+        return VolumeInfo::default();
+    };
+    let mounts = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    let Some((device, filesystem)) = mount_of(&mounts, &path) else {
+        return VolumeInfo::default();
+    };
+
+    let device = std::fs::canonicalize(device).ok();
+    let label = std::fs::read_dir("/dev/disk/by-label")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .find(|link| device.is_some() && std::fs::canonicalize(link.path()).ok() == device)
+        .map(|link| unescape_udev(&link.file_name().to_string_lossy()));
+
+    VolumeInfo {
+        label,
+        filesystem: Some(filesystem.to_owned()),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn volume_info(_path: &Path) -> VolumeInfo {
+    VolumeInfo::default()
+}
+
+/// Filesystems that only take names Windows allows, as Linux and macOS
+/// report them. `fuseblk` is what NTFS mounted through ntfs-3g shows up as.
+const WINDOWS_FILESYSTEMS: &[&str] = &[
+    "vfat", "msdos", "fat", "fat32", "exfat", "ntfs", "ntfs3", "fuseblk",
+];
+
+/// Whether `path` is on a FAT, exFAT or NTFS volume. The path may not exist
+/// yet, so the nearest existing directory above it decides.
+pub fn has_windows_names(path: &Path) -> bool {
+    let Some(existing) = path.ancestors().find(|dir| dir.exists()) else {
+        return false;
+    };
+    volume_info(existing).filesystem.is_some_and(|filesystem| {
+        WINDOWS_FILESYSTEMS.contains(&filesystem.to_ascii_lowercase().as_str())
+    })
+}
+
+/// The device and filesystem type `path` is mounted from according to the
+/// mount table `mounts`, in the format of `/proc/self/mounts`. The deepest
+/// mount point above the path is the one it is on.
+#[cfg(target_os = "linux")]
+fn mount_of<'a>(mounts: &'a str, path: &Path) -> Option<(String, &'a str)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let (device, point, filesystem) = (fields.next()?, fields.next()?, fields.next()?);
+            Some((unescape_mount(device), unescape_mount(point), filesystem))
+        })
+        .filter(|(_, point, _)| path.starts_with(point))
+        .max_by_key(|(_, point, _)| point.len())
+        .map(|(device, _, filesystem)| (device, filesystem))
+}
+
+/// Undoes the octal escapes, like `\040` for a space, of the mount table.
+#[cfg(target_os = "linux")]
+fn unescape_mount(field: &str) -> String {
+    unescape(field, '\\', 3, 8)
+}
+
+/// Undoes the hex escapes, like `\x20` for a space, of udev's link names.
+#[cfg(target_os = "linux")]
+fn unescape_udev(name: &str) -> String {
+    unescape(&name.replace("\\x", "\\"), '\\', 2, 16)
+}
+
+#[cfg(target_os = "linux")]
+fn unescape(text: &str, escape: char, digits: usize, radix: u32) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find(escape) {
+        bytes.extend_from_slice(&rest.as_bytes()[..at]);
+        let code = rest.get(at + 1..at + 1 + digits);
+        match code.and_then(|code| u8::from_str_radix(code, radix).ok()) {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &rest[at + 1 + digits..];
+            }
+            None => {
+                bytes.push(b'\\');
+                rest = &rest[at + 1..];
+            }
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Unix mode bits and ownership of a file, as kept by
+/// `--preserve-permissions`.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct UnixPermissions {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+#[cfg(unix)]
+pub fn read_permissions(path: &Path) -> io::Result<Option<UnixPermissions>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+    Ok(Some(UnixPermissions {
+        mode: metadata.mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+    }))
+}
+
+/// Windows has ACLs instead, which aren't carried over.
+#[cfg(windows)]
+pub fn read_permissions(_path: &Path) -> io::Result<Option<UnixPermissions>> {
+    Ok(None)
+}
+
+/// Sets the mode bits of `path`, and its owner and group too when running
+/// as root, as nobody else may hand files to other users.
+#[cfg(unix)]
+pub fn apply_permissions(path: &Path, permissions: &UnixPermissions) -> io::Result<()> {
+    use std::os::unix::fs::{chown, PermissionsExt};
+
+    // ownership first, changing it can clear the setuid and setgid bits
+    if unsafe { libc::geteuid() } == 0 {
+        chown(path, Some(permissions.uid), Some(permissions.gid))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(permissions.mode))
+}
+
+#[cfg(windows)]
+pub fn apply_permissions(_path: &Path, _permissions: &UnixPermissions) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writes a file's pages out and has the kernel drop them from its page
+/// cache, so the next read comes from the disk rather than memory.
+#[cfg(target_os = "linux")]
+pub fn evict_cached(path: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    // dirty pages would stay cached
+    file.sync_data()?;
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Other systems offer no way to do this for a single file without opening
+/// it unbuffered, so reads may still be served from memory.
+#[cfg(not(target_os = "linux"))]
+pub fn evict_cached(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// A disk image attached read-only for `--mount-images`, detached again
+/// when dropped, also when scanning it failed halfway.
+#[cfg_attr(unix, allow(dead_code))]
+pub struct MountedImage {
+    image: PathBuf,
+    /// Where the image's first volume can be walked.
+    pub root: PathBuf,
+}
+
+/// Attaches a `.vhd`, `.vhdx` or `.iso` with `Mount-DiskImage`, read-only,
+/// and finds the drive letter of its first volume. Images without one, or
+/// those already attached elsewhere like to a running virtual machine,
+/// fail and are left as they were.
+#[cfg(windows)]
+pub fn mount_image(image: &Path) -> anyhow::Result<MountedImage> {
+    // PowerShell single quotes only need their own doubling
+    let quoted = format!("'{}'", image.display().to_string().replace('\'', "''"));
+    let mut mounted = MountedImage {
+        image: image.to_owned(),
+        root: PathBuf::new(),
+    };
+    // optical images have their volume directly, virtual disks through a
+    // partition
+    let script = format!(
+        "$ErrorActionPreference = 'Stop'; \
+         $image = Mount-DiskImage -ImagePath {} -Access ReadOnly -PassThru; \
+         if ($image.StorageType -eq 1) {{ $volume = $image | Get-Volume }} \
+         else {{ $volume = $image | Get-Disk | Get-Partition | Get-Volume }}; \
+         ($volume | Where-Object DriveLetter | Select-Object -First 1).DriveLetter",
+        quoted
+    );
+    let output =
+        powershell(&script).with_context(|| format!("failed to mount `{}`", image.display()))?;
+    let letter = output.trim();
+    if letter.len() != 1 {
+        bail!("`{}` has no volume with a drive letter", image.display());
+    }
+    mounted.root = PathBuf::from(format!("{}:\\", letter));
+
+    Ok(mounted)
+}
+
+#[cfg(unix)]
+pub fn mount_image(image: &Path) -> anyhow::Result<MountedImage> {
+    bail!(
+        "can't mount `{}`, disk images are only mounted on Windows",
+        image.display()
+    )
+}
+
+#[cfg(windows)]
+impl Drop for MountedImage {
+    fn drop(&mut self) {
+        let quoted = format!("'{}'", self.image.display().to_string().replace('\'', "''"));
+        if let Err(err) = powershell(&format!(
+            "Dismount-DiskImage -ImagePath {} | Out-Null",
+            quoted
+        )) {
+            eprintln!(
+                "warning: failed to dismount `{}`, {:#}",
+                self.image.display(),
+                err
+            );
+        }
+    }
+}
+
+/// A volume shadow copy taken for `--use-vss`, a frozen view of a volume
+/// that can be read even where the live files are locked, like
+/// `NTUSER.DAT` or a database that is open. Deleted again when dropped, so
+/// a failed or cancelled run doesn't leave it taking up space.
+#[cfg_attr(unix, allow(dead_code))]
+pub struct VssSnapshot {
+    id: String,
+    /// The volume the snapshot was taken of, e.g. `C:\`.
+    pub volume: PathBuf,
+    /// Where the snapshot can be read, e.g.
+    /// `\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3\`.
+    pub device: PathBuf,
+}
+
+/// Takes a shadow copy of each volume holding one of `roots`. Roots that
+/// don't exist, like unused drive letters, are left out.
+#[cfg(windows)]
+pub fn snapshot_volumes(roots: &[PathBuf]) -> anyhow::Result<Vec<VssSnapshot>> {
+    let mut volumes = roots
+        .iter()
+        .filter_map(|root| volume_root(root))
+        .collect::<Vec<_>>();
+    volumes.sort();
+    volumes.dedup();
+    // those taken already are deleted again if a later one fails
+    volumes
+        .iter()
+        .map(|volume| create_snapshot(Path::new(volume)))
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn snapshot_volumes(_roots: &[PathBuf]) -> anyhow::Result<Vec<VssSnapshot>> {
+    bail!("volume shadow copies are only available on Windows")
+}
+
+/// Takes a shadow copy of `volume` through the `Win32_ShadowCopy` WMI
+/// class. Only administrators may create one.
+#[cfg(windows)]
+fn create_snapshot(volume: &Path) -> anyhow::Result<VssSnapshot> {
+    let quoted = format!("'{}'", volume.display().to_string().replace('\'', "''"));
+    let script = format!(
+        "$ErrorActionPreference = 'Stop'; \
+         $result = Invoke-CimMethod -ClassName Win32_ShadowCopy -MethodName Create \
+         -Arguments @{{ Volume = {}; Context = 'ClientAccessible' }}; \
+         if ($result.ReturnValue -ne 0) {{ throw \"error $($result.ReturnValue)\" }}; \
+         $result.ShadowID; \
+         (Get-CimInstance Win32_ShadowCopy -Filter \"ID='$($result.ShadowID)'\").DeviceObject",
+        quoted
+    );
+    let output = powershell(&script)
+        .with_context(|| format!("failed to create a shadow copy of `{}`", volume.display()))?;
+    let mut lines = output.lines().map(str::trim);
+    let id = lines.next().unwrap_or_default();
+    if id.is_empty() {
+        bail!("failed to create a shadow copy of `{}`", volume.display());
+    }
+    // owned from here on, so it is deleted if its device can't be found
+    let mut snapshot = VssSnapshot {
+        id: id.to_owned(),
+        volume: volume.to_owned(),
+        device: PathBuf::new(),
+    };
+    match lines.next() {
+        Some(device) if !device.is_empty() => {
+            snapshot.device = PathBuf::from(format!("{}\\", device))
+        }
+        _ => bail!("shadow copy of `{}` has no device", volume.display()),
+    }
+
+    Ok(snapshot)
+}
+
+#[cfg(windows)]
+impl Drop for VssSnapshot {
+    fn drop(&mut self) {
+        if let Err(err) = powershell(&format!(
+            "Get-CimInstance Win32_ShadowCopy -Filter \"ID='{}'\" | Remove-CimInstance",
+            self.id
+        )) {
+            eprintln!(
+                "warning: failed to delete the shadow copy of `{}`, {:#}",
+                self.volume.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Volumes and the devices of their snapshots, see [`snapshot_path`].
+static SNAPSHOTS: OnceCell<Vec<(PathBuf, PathBuf)>> = OnceCell::new();
+
+/// Makes [`snapshot_path`] read from `snapshots` for the rest of the run.
+/// Only the first call has an effect.
+pub fn install_snapshots(snapshots: &[VssSnapshot]) {
+    let _ = SNAPSHOTS.set(
+        snapshots
+            .iter()
+            .map(|snapshot| (snapshot.volume.clone(), snapshot.device.clone()))
+            .collect(),
+    );
+}
+
+/// Where to read `path` from: the same file in the shadow copy of its
+/// volume if one was taken, otherwise `path` itself. A volume mounted in a
+/// folder of another one is matched before the one holding the folder.
+pub fn snapshot_path(path: &Path) -> PathBuf {
+    path_in_snapshots(SNAPSHOTS.get().map_or(&[], Vec::as_slice), path)
+}
+
+/// [`snapshot_path`] with the volumes and devices given.
+fn path_in_snapshots(snapshots: &[(PathBuf, PathBuf)], path: &Path) -> PathBuf {
+    snapshots
+        .iter()
+        .filter_map(|(volume, device)| Some((volume, device, path.strip_prefix(volume).ok()?)))
+        .max_by_key(|(volume, ..)| volume.components().count())
+        .map_or_else(|| path.to_owned(), |(_, device, rest)| device.join(rest))
+}
+
+/// Runs a PowerShell command, returning what it printed.
+#[cfg(windows)]
+fn powershell(script: &str) -> anyhow::Result<String> {
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+        .context("failed to run PowerShell")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, TempTree};
+
+    #[test]
+    fn directories_on_another_device_are_pruned() {
+        let tree = TempTree::new();
+        let dir = entry(&tree.dir("nested"));
+        let file = entry(&tree.file("nested/file.txt", "x"));
+        let dev = device_id(tree.path()).unwrap();
+        // a device the tree isn't on stands in for another filesystem
+        let other = dev.wrapping_add(1);
+
+        assert!(same_filesystem(dev, &dir));
+        assert!(!same_filesystem(other, &dir));
+        // files can't lead anywhere, only directories are compared
+        assert!(same_filesystem(other, &file));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn junctions_are_told_apart_from_symlinks() {
+        let tree = TempTree::new();
+        let target = tree.dir("target");
+        let junction = tree.path().join("junction");
+        let made = std::process::Command::new("cmd")
+            .arg("/C")
+            .arg("mklink")
+            .arg("/J")
+            .arg(&junction)
+            .arg(&target)
+            .output()
+            .unwrap();
+        assert!(made.status.success());
+        assert!(is_junction(&junction));
+        assert!(!is_junction(&target));
+
+        // symlinks need a privilege or developer mode
+        let link = tree.path().join("symlink");
+        if std::os::windows::fs::symlink_dir(&target, &link).is_ok() {
+            assert!(!is_junction(&link));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_no_junctions() {
+        let tree = TempTree::new();
+        let target = tree.dir("target");
+        let link = tree.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(!is_junction(&link));
+        assert!(!is_junction(&target));
+    }
+
+    #[test]
+    fn free_space_is_queried_on_the_nearest_existing_ancestor() {
+        let tree = TempTree::new();
+        let free = free_space(tree.path()).unwrap();
+        assert!(free > 0);
+        let missing = free_space(&tree.path().join("not/yet/created")).unwrap();
+        // other processes may write in between, the volume is the same
+        assert!(missing.abs_diff(free) < 1 << 30);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dotfiles_are_hidden_and_nothing_is_system() {
+        let tree = TempTree::new();
+        let dotfile = entry(&tree.file(".profile", ""));
+        let plain = entry(&tree.file("profile", ""));
+        assert!(is_hidden(&dotfile));
+        assert!(!is_hidden(&plain));
+        assert!(!is_system(&dotfile));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn hidden_and_system_attributes_are_read() {
+        let tree = TempTree::new();
+        let hidden = tree.file("hidden.txt", "");
+        let system = tree.file("system.txt", "");
+        let plain = tree.file(".plain", "");
+        for (path, flag) in [(&hidden, "+h"), (&system, "+s")] {
+            let status = std::process::Command::new("attrib")
+                .arg(flag)
+                .arg(path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        assert!(is_hidden(&entry(&hidden)));
+        assert!(!is_system(&entry(&hidden)));
+        assert!(is_system(&entry(&system)));
+        // a leading dot means nothing to Windows
+        assert!(!is_hidden(&entry(&plain)));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn sharing_violations_are_locked_files() {
+        assert!(is_locked(&io::Error::from_raw_os_error(32)));
+        assert!(is_locked(&io::Error::from_raw_os_error(33)));
+        assert!(!is_locked(&io::Error::from_raw_os_error(5)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_files_are_never_locked() {
+        assert!(!is_locked(&io::Error::from_raw_os_error(libc::EACCES)));
+        assert!(!is_locked(&io::Error::from_raw_os_error(libc::EWOULDBLOCK)));
+    }
+
+    #[test]
+    fn streams_are_opened_by_name_after_a_colon() {
+        assert_eq!(
+            stream_path(Path::new("C:\\Downloads\\setup.exe"), "Zone.Identifier"),
+            Path::new("C:\\Downloads\\setup.exe:Zone.Identifier")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn alternate_data_streams_are_listed_with_their_size() {
+        let tree = TempTree::new();
+        let file = tree.file("setup.exe", "MZ");
+        let zone = "[ZoneTransfer]\r\nZoneId=3\r\n";
+        std::fs::write(stream_path(&file, "Zone.Identifier"), zone).unwrap();
+
+        assert_eq!(
+            list_ads(&file),
+            [("Zone.Identifier".to_owned(), zone.len() as u64)]
+        );
+        assert!(list_ads(&tree.file("plain.txt", "")).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_files_have_no_streams() {
+        let tree = TempTree::new();
+        assert!(list_ads(&tree.file("plain.txt", "")).is_empty());
+    }
+
+    #[test]
+    fn volumes_are_described_by_what_is_known() {
+        let info = |label: Option<&str>, filesystem: Option<&str>| {
+            VolumeInfo {
+                label: label.map(str::to_owned),
+                filesystem: filesystem.map(str::to_owned),
+            }
+            .to_string()
+        };
+        assert_eq!(info(Some("Backup"), Some("NTFS")), "\"Backup\" (NTFS)");
+        assert_eq!(info(Some("Backup"), None), "\"Backup\"");
+        assert_eq!(info(None, Some("ext4")), "(ext4)");
+        assert_eq!(info(None, None), "(unknown)");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn the_deepest_mount_above_a_path_is_its_volume() {
+        let mounts = "\
+/dev/sda2 / ext4 rw,relatime 0 0
+proc /proc proc rw 0 0
+/dev/sdb1 /media/me/My\\040Backup fuseblk rw 0 0
+/dev/sdb2 /media/me/My\\040Backup/photos vfat rw 0 0
+";
+        let mount = |path: &str| mount_of(mounts, Path::new(path));
+        assert_eq!(mount("/home/me"), Some(("/dev/sda2".to_owned(), "ext4")));
+        assert_eq!(
+            mount("/media/me/My Backup/docs"),
+            Some(("/dev/sdb1".to_owned(), "fuseblk"))
+        );
+        assert_eq!(
+            mount("/media/me/My Backup/photos/2024"),
+            Some(("/dev/sdb2".to_owned(), "vfat"))
+        );
+        // a mount point merely sharing a prefix doesn't count
+        assert_eq!(
+            mount("/media/me/My Backup2"),
+            Some(("/dev/sda2".to_owned(), "ext4"))
+        );
+        assert_eq!(mount_of("", Path::new("/")), None);
+
+        assert_eq!(unescape_udev("My\\x20Backup"), "My Backup");
+        assert_eq!(unescape_mount("a\\134b"), "a\\b");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn the_volume_of_a_real_path_has_a_filesystem() {
+        let tree = TempTree::new();
+        assert!(volume_info(tree.path()).filesystem.is_some());
+        assert!(volume_info(&tree.path().join("missing"))
+            .filesystem
+            .is_none());
+    }
+
+    #[test]
+    fn reparse_tags_are_named_or_hexadecimal() {
+        assert_eq!(parse_reparse_tag("dedup").unwrap(), IO_REPARSE_TAG_DEDUP);
+        assert_eq!(parse_reparse_tag(" Cloud ").unwrap(), IO_REPARSE_TAG_CLOUD);
+        assert_eq!(
+            parse_reparse_tag("0x80000013").unwrap(),
+            IO_REPARSE_TAG_DEDUP
+        );
+        assert_eq!(
+            parse_reparse_tag("0XA000000C").unwrap(),
+            IO_REPARSE_TAG_SYMLINK
+        );
+        // every OneDrive variant counts as `cloud`
+        assert_eq!(
+            parse_reparse_tag("0x9000701a").unwrap(),
+            IO_REPARSE_TAG_CLOUD
+        );
+        assert_ne!(
+            parse_reparse_tag("dedup").unwrap(),
+            parse_reparse_tag("cloud").unwrap()
+        );
+        assert!(parse_reparse_tag("0xnope").is_err());
+        let err = parse_reparse_tag("onedrive").unwrap_err().to_string();
+        assert!(err.contains("dedup, hsm"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nothing_is_a_reparse_point_off_windows() {
+        let tree = TempTree::new();
+        let file = tree.file("file.txt", "text");
+        assert_eq!(entry_reparse_tag(&entry(&file)), None);
+    }
+
+    #[test]
+    fn paths_are_read_from_the_snapshot_of_their_volume() {
+        let snapshots = [
+            (PathBuf::from("/vol/c"), PathBuf::from("/shadow/1")),
+            // a volume mounted in a folder of the one above
+            (PathBuf::from("/vol/c/data"), PathBuf::from("/shadow/2")),
+        ];
+
+        assert_eq!(
+            path_in_snapshots(&snapshots, Path::new("/vol/c/Users/me/NTUSER.DAT")),
+            Path::new("/shadow/1/Users/me/NTUSER.DAT")
+        );
+        assert_eq!(
+            path_in_snapshots(&snapshots, Path::new("/vol/c/data/app.db")),
+            Path::new("/shadow/2/app.db")
+        );
+        // only whole components count, and other volumes are read live
+        assert_eq!(
+            path_in_snapshots(&snapshots, Path::new("/vol/cd/file")),
+            Path::new("/vol/cd/file")
+        );
+        assert_eq!(
+            path_in_snapshots(&[], Path::new("/vol/c/file")),
+            Path::new("/vol/c/file")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shadow_copies_need_windows() {
+        let error = snapshot_volumes(&[PathBuf::from("/")]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "volume shadow copies are only available on Windows"
+        );
+        // nothing was installed, so every path is read live
+        assert_eq!(
+            snapshot_path(Path::new("/etc/hosts")),
+            Path::new("/etc/hosts")
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+
+/// Estimates the time per file as a moving average.
+///
+/// The first samples are averaged evenly so the estimate settles quickly,
+/// after that each new sample only has a small weight, which smooths out
+/// the spikes caused by the occasional large or slow file.
+#[derive(Default)]
+pub struct RateEstimator {
+    secs_per_file: f64,
+    samples: u64,
+}
+
+impl RateEstimator {
+    /// Weight of a new sample once enough samples have been seen.
+    const MIN_WEIGHT: f64 = 0.02;
+
+    /// Records the time it took to process one file.
+    pub fn record(&mut self, elapsed: Duration) {
+        self.samples += 1;
+        #[allow(clippy::cast_precision_loss)]
+        let weight = (1.0 / self.samples as f64).max(Self::MIN_WEIGHT);
+        self.secs_per_file += weight * (elapsed.as_secs_f64() - self.secs_per_file);
+    }
+
+    /// Expected time to process `remaining` more files, if anything has been
+    /// measured yet.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        #[allow(clippy::cast_precision_loss)]
+        let secs = self.secs_per_file * remaining as f64;
+        (self.samples > 0).then(|| Duration::from_secs_f64(secs))
+    }
+}
+
+/// Formats a duration coarsely, e.g. `45s`, `2m13s` or `1h05m`.
+pub fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+/// Progress shown on stderr while scanning.
+///
+/// With a known total this is a bar with an ETA, otherwise a spinner
+/// counting the files seen so far. When disabled, nothing is drawn.
+pub struct Progress {
+    bar: ProgressBar,
+    /// The rate and when the previous file finished, shared between the
+    /// walker and the thread printing results.
+    timing: Mutex<(RateEstimator, Instant)>,
+    /// Where progress is written to as well, with `--progress-to`.
+    log: Mutex<Option<ProgressLog>>,
+}
+
+/// How far the scan is, for [`ProgressLog`] to read from its own thread.
+#[derive(Default)]
+struct Counters {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    /// The entry handled last.
+    current: Mutex<PathBuf>,
+}
+
+/// Appends a line on how far the scan is to a file at a fixed interval, for
+/// runs nobody watches, and a last one once it is done.
+struct ProgressLog {
+    counters: Arc<Counters>,
+    /// Dropped to stop the thread writing.
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl ProgressLog {
+    fn start(path: &Path, interval: Duration, total: Option<u64>) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let counters = Arc::new(Counters::default());
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn({
+            let counters = Arc::clone(&counters);
+            move || loop {
+                let done = stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout);
+                // a full disk or a removed file shouldn't stop the scan, the
+                // log is only an aid to watching it
+                let _ = write_snapshot(&mut file, &counters, total);
+                if done {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            counters,
+            stop,
+            thread,
+        })
+    }
+
+    fn finish(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+/// Writes one line like
+/// `2026-01-31T12:00:00Z files=1200/5000 bytes=73400320 current=C:\Users\a.txt`,
+/// the total only if files were counted first.
+fn write_snapshot(file: &mut File, counters: &Counters, total: Option<u64>) -> io::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let files = counters.files.load(Ordering::Relaxed);
+    let current = counters
+        .current
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    writeln!(
+        file,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z files={}{} bytes={} current={}",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        files,
+        total.map_or(String::new(), |total| format!("/{}", total)),
+        counters.bytes.load(Ordering::Relaxed),
+        current.display()
+    )?;
+    file.flush()
+}
+
+impl Progress {
+    pub fn new(enabled: bool, total: Option<u64>) -> Self {
+        let bar = match (enabled, total) {
+            // nothing is drawn, but the total still goes to the log
+            (false, total) => ProgressBar::with_draw_target(total, ProgressDrawTarget::hidden()),
+            (true, Some(total)) => ProgressBar::new(total).with_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} files {msg}")
+                    .expect("valid template"),
+            ),
+            (true, None) => {
+                let bar = ProgressBar::new_spinner().with_style(
+                    ProgressStyle::with_template("{spinner} {pos} files").expect("valid template"),
+                );
+                bar.enable_steady_tick(Duration::from_millis(100));
+                bar
+            }
+        };
+
+        Self {
+            bar,
+            timing: Mutex::new((RateEstimator::default(), Instant::now())),
+            log: Mutex::new(None),
+        }
+    }
+
+    /// Also appends progress to the file at `path` every `interval`, from a
+    /// thread of its own, until [`Self::finish`].
+    pub fn log_to(&self, path: &Path, interval: Duration) -> io::Result<()> {
+        let log = ProgressLog::start(path, interval, self.bar.length())?;
+        *self.log.lock().unwrap_or_else(|err| err.into_inner()) = Some(log);
+        Ok(())
+    }
+
+    /// Records the entry being handled, for the progress log.
+    pub fn set_current(&self, path: &Path) {
+        let log = self.log.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(log) = &*log {
+            *log.counters
+                .current
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()) = path.to_owned();
+        }
+    }
+
+    /// Advances the progress by one processed file of `size` bytes.
+    pub fn file_done(&self, size: u64) {
+        if let Some(log) = &*self.log.lock().unwrap_or_else(|err| err.into_inner()) {
+            log.counters.files.fetch_add(1, Ordering::Relaxed);
+            log.counters.bytes.fetch_add(size, Ordering::Relaxed);
+        }
+
+        let mut timing = self.timing.lock().unwrap_or_else(|err| err.into_inner());
+        let (rate, last) = &mut *timing;
+        let now = Instant::now();
+        rate.record(now - *last);
+        *last = now;
+        self.bar.inc(1);
+
+        if let Some(total) = self.bar.length() {
+            let remaining = total.saturating_sub(self.bar.position());
+            if let Some(eta) = rate.eta(remaining) {
+                self.bar.set_message(format!("ETA {}", format_eta(eta)));
+            }
+        }
+    }
+
+    /// Prints a result line without tearing the progress display.
+    pub fn println(&self, line: impl Display) {
+        self.suspend(|| println!("{}", line));
+    }
+
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bar.suspend(f)
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        if let Some(log) = self
+            .log
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take()
+        {
+            log.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempTree;
+    use std::fs;
+
+    #[test]
+    fn nothing_is_estimated_before_the_first_file() {
+        assert_eq!(RateEstimator::default().eta(10), None);
+    }
+
+    #[test]
+    fn first_samples_are_averaged_evenly() {
+        let mut rate = RateEstimator::default();
+        rate.record(Duration::from_secs(1));
+        rate.record(Duration::from_secs(3));
+        assert_eq!(rate.eta(10), Some(Duration::from_secs(20)));
+        assert_eq!(rate.eta(0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn a_single_slow_file_barely_moves_a_settled_estimate() {
+        let mut rate = RateEstimator::default();
+        for _ in 0..1000 {
+            rate.record(Duration::from_millis(10));
+        }
+        rate.record(Duration::from_secs(60));
+
+        let eta = rate.eta(100).unwrap();
+        assert!(eta < Duration::from_secs(150), "{:?}", eta);
+        for _ in 0..1000 {
+            rate.record(Duration::from_millis(10));
+        }
+        let eta = rate.eta(100).unwrap();
+        assert!(eta.abs_diff(Duration::from_secs(1)) < Duration::from_millis(10));
+    }
+
+    #[test]
+    fn etas_are_formatted_coarsely() {
+        assert_eq!(format_eta(Duration::from_millis(45_900)), "45s");
+        assert_eq!(format_eta(Duration::from_secs(133)), "2m13s");
+        assert_eq!(format_eta(Duration::from_secs(3900)), "1h05m");
+    }
+
+    #[test]
+    fn progress_is_logged_every_interval_and_once_at_the_end() {
+        let tree = TempTree::new();
+        let log = tree.path().join("progress.log");
+        let progress = Progress::new(false, Some(10));
+        progress.log_to(&log, Duration::from_millis(100)).unwrap();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            progress.set_current(Path::new(file));
+            progress.file_done(1000);
+        }
+        thread::sleep(Duration::from_millis(450));
+        progress.finish();
+
+        let written = fs::read_to_string(&log).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        // four ticks and the last line, give or take a slow tick
+        assert!((4..=6).contains(&lines.len()), "{}", written);
+        for line in &lines {
+            let (stamp, rest) = line.split_once(' ').unwrap();
+            assert!(stamp.ends_with('Z') && stamp.contains('T'), "{}", line);
+            assert_eq!(rest, "files=3/10 bytes=3000 current=c.txt");
+        }
+
+        // a later run appends to the same log
+        let progress = Progress::new(false, None);
+        progress.log_to(&log, Duration::from_secs(60)).unwrap();
+        progress.file_done(5);
+        progress.finish();
+        let written = fs::read_to_string(&log).unwrap();
+        assert_eq!(written.lines().count(), lines.len() + 1);
+        assert!(
+            written.ends_with(" files=1 bytes=5 current=\n"),
+            "{}",
+            written
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use time::OffsetDateTime;
+use walkdir::DirEntry;
+
+/// Seconds between the FILETIME epoch, 1601, and the Unix one.
+const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+/// What the `$I` index file of a recycled file records about it.
+pub struct RecycledInfo {
+    /// Where the file was deleted from.
+    pub original: PathBuf,
+    /// When it was deleted, as `YYYY-MM-DD HH:MM` in UTC.
+    pub deleted: Option<String>,
+}
+
+impl RecycledInfo {
+    /// The original's file name. The path is a Windows one, which other
+    /// systems wouldn't split at its backslashes.
+    pub fn file_name(&self) -> PathBuf {
+        let path = self.original.to_string_lossy();
+        PathBuf::from(path.rsplit(['\\', '/']).next().unwrap_or_default())
+    }
+}
+
+/// Whether this is a recycle bin folder, `$Recycle.Bin` since Vista or
+/// `RECYCLER` before.
+pub fn is_recycle_bin(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir()
+        && entry.file_name().to_str().is_some_and(|name| {
+            name.eq_ignore_ascii_case("$recycle.bin") || name.eq_ignore_ascii_case("recycler")
+        })
+}
+
+/// Whether `path` lies inside a recycle bin.
+pub fn in_recycle_bin(path: &Path) -> bool {
+    path.ancestors().skip(1).any(|dir| {
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.eq_ignore_ascii_case("$recycle.bin"))
+    })
+}
+
+/// The `$I` index file next to the `$R` data file at `path`, e.g.
+/// `$IA1B2C3.docx` for `$RA1B2C3.docx`. Anything else yields nothing.
+pub fn index_path_for(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let id = name.strip_prefix("$R")?;
+    Some(path.with_file_name(format!("$I{}", id)))
+}
+
+/// Whether the file at `path` is the `$I` index of a recycled file rather
+/// than its data.
+pub fn is_index_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("$I"))
+        && in_recycle_bin(path)
+}
+
+/// Reads the `$I` index file belonging to the `$R` data file at `path`.
+pub fn read_recycled(path: &Path) -> Option<RecycledInfo> {
+    if !in_recycle_bin(path) {
+        return None;
+    }
+    parse_index(&fs::read(index_path_for(path)?).ok()?)
+}
+
+/// Parses a `$I` index record. Vista to 8.1 write version 1, with the path
+/// in a fixed 260 character field; Windows 10 writes version 2, with the
+/// path's length in front of it. Both store UTF-16LE.
+pub fn parse_index(data: &[u8]) -> Option<RecycledInfo> {
+    let u64_at = |at: usize| Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?));
+
+    // the size the file had comes next, the data file tells that already
+    let version = u64_at(0)?;
+    let filetime = u64_at(16)?;
+    let name = match version {
+        1 => data.get(24..24 + 520)?,
+        2 => {
+            let len = u32::from_le_bytes(data.get(24..28)?.try_into().ok()?) as usize;
+            data.get(28..28 + len.checked_mul(2)?)?
+        }
+        _ => return None,
+    };
+
+    let units = name
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect::<Vec<_>>();
+    let original = String::from_utf16(&units).ok()?;
+    if original.is_empty() {
+        return None;
+    }
+
+    Some(RecycledInfo {
+        original: PathBuf::from(original),
+        deleted: format_filetime(filetime),
+    })
+}
+
+fn format_filetime(filetime: u64) -> Option<String> {
+    let seconds = i64::try_from(filetime / 10_000_000).ok()? - FILETIME_UNIX_OFFSET;
+    let time = OffsetDateTime::from_unix_timestamp(seconds).ok()?;
+    Some(format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::recycle_index;
+
+    /// 2024-01-31 10:00 UTC.
+    const DELETED: u64 = 133_511_688_000_000_000;
+
+    #[test]
+    fn both_index_versions_give_the_original_path() {
+        let info =
+            parse_index(&recycle_index(r"C:\Users\me\Documents\taxes.xlsx", DELETED)).unwrap();
+        assert_eq!(
+            info.original,
+            Path::new(r"C:\Users\me\Documents\taxes.xlsx")
+        );
+        assert_eq!(info.file_name(), Path::new("taxes.xlsx"));
+        assert_eq!(info.deleted.as_deref(), Some("2024-01-31 10:00"));
+
+        // version 1 has the path in a fixed field of 260 characters
+        let mut v1 = [1u64, 100, DELETED]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let name = r"D:\notes.txt".encode_utf16().collect::<Vec<_>>();
+        v1.extend(name.iter().flat_map(|unit| unit.to_le_bytes()));
+        v1.resize(24 + 520, 0);
+        let info = parse_index(&v1).unwrap();
+        assert_eq!(info.original, Path::new(r"D:\notes.txt"));
+        assert_eq!(info.file_name(), Path::new("notes.txt"));
+    }
+
+    #[test]
+    fn broken_index_records_yield_nothing() {
+        let record = recycle_index(r"C:\a.txt", DELETED);
+        assert!(parse_index(&record[..30]).is_none());
+        assert!(parse_index(&recycle_index("", DELETED)).is_none());
+        let mut unknown = record.clone();
+        unknown[0] = 3;
+        assert!(parse_index(&unknown).is_none());
+        // a length running past the end
+        let mut long = record;
+        long[24..28].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(parse_index(&long).is_none());
+    }
+
+    #[test]
+    fn data_files_pair_with_their_index() {
+        let bin = Path::new("$Recycle.Bin/S-1-5-21");
+        assert_eq!(
+            index_path_for(&bin.join("$RA1B2C3.docx")).unwrap(),
+            bin.join("$IA1B2C3.docx")
+        );
+        assert!(index_path_for(&bin.join("desktop.ini")).is_none());
+        assert!(is_index_file(&bin.join("$IA1B2C3.docx")));
+        assert!(!is_index_file(&bin.join("$RA1B2C3.docx")));
+        assert!(!is_index_file(Path::new("elsewhere/$IA1B2C3.docx")));
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{bail, Context};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+use crate::{config::ensure_contained, magic::has_magic, FileClassification};
+
+/// A user-defined classification, loaded from a `--rules` file.
+///
+/// Exactly one of `extension`, `filename` or `glob` selects the files the
+/// rule applies to; `content_prefix` additionally requires the file to start
+/// with the given text, so such rules never match under `--classify-only`.
+/// Matching is case-insensitive like the built-in rules.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomRule {
+    extension: Option<String>,
+    filename: Option<String>,
+    /// Matched against the file name, or against the full path if the
+    /// pattern contains a separator. Supports `*` and `?`.
+    glob: Option<String>,
+    content_prefix: Option<String>,
+    label: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<CustomRule>,
+}
+
+static CUSTOM_RULES: OnceCell<Vec<CustomRule>> = OnceCell::new();
+
+/// Loads rules from a TOML file of `[[rule]]` tables.
+pub fn load_rules(path: &Path) -> anyhow::Result<Vec<CustomRule>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read rules `{}`", path.display()))?;
+    let mut file: RulesFile = toml::from_str(&text)
+        .with_context(|| format!("failed to parse rules `{}`", path.display()))?;
+
+    for (index, rule) in file.rules.iter_mut().enumerate() {
+        rule.validate()
+            .with_context(|| format!("invalid rule #{} in `{}`", index + 1, path.display()))?;
+    }
+
+    Ok(file.rules)
+}
+
+/// Makes `rules` take precedence over the built-in classification for the
+/// rest of the run. Only the first call has an effect.
+pub fn install_rules(rules: Vec<CustomRule>) {
+    let _ = CUSTOM_RULES.set(rules);
+}
+
+/// Labels of the installed rules, which act as additional categories.
+pub fn custom_labels() -> impl Iterator<Item = &'static str> {
+    CUSTOM_RULES
+        .get()
+        .into_iter()
+        .flatten()
+        .map(|rule| rule.label.as_str())
+}
+
+/// Classifies `path` by the first installed rule that matches it. Rules
+/// with a `content_prefix` never match unless `read_contents` is set.
+pub fn classify_custom(path: &Path, read_contents: bool) -> Option<FileClassification> {
+    CUSTOM_RULES
+        .get()?
+        .iter()
+        .find(|rule| rule.matches(path, read_contents))
+        .map(|rule| FileClassification::Custom(rule.label.clone()))
+}
+
+impl CustomRule {
+    fn validate(&mut self) -> anyhow::Result<()> {
+        let patterns = [&self.extension, &self.filename, &self.glob];
+        if patterns.iter().filter(|pattern| pattern.is_some()).count() != 1 {
+            bail!("expected exactly one of `extension`, `filename` or `glob`");
+        }
+
+        // the label doubles as a folder name in the `by-category` layout
+        ensure_contained(Path::new(&self.label)).context("invalid label")?;
+
+        for pattern in [&mut self.extension, &mut self.filename, &mut self.glob]
+            .into_iter()
+            .flatten()
+        {
+            *pattern = pattern.trim_start_matches('.').to_ascii_lowercase();
+        }
+        if let Some(glob) = &mut self.glob {
+            *glob = glob.replace('\\', "/");
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, path: &Path, read_contents: bool) -> bool {
+        let lower = |part: Option<&std::ffi::OsStr>| {
+            part.map(|part| part.to_string_lossy().to_ascii_lowercase())
+        };
+
+        let name_matches = if let Some(extension) = &self.extension {
+            lower(path.extension()).as_deref() == Some(extension)
+        } else if let Some(filename) = &self.filename {
+            lower(path.file_name()).as_deref() == Some(filename)
+        } else if let Some(glob) = &self.glob {
+            let subject = if glob.contains('/') {
+                Some(
+                    path.to_string_lossy()
+                        .to_ascii_lowercase()
+                        .replace('\\', "/"),
+                )
+            } else {
+                lower(path.file_name())
+            };
+            subject.is_some_and(|subject| wildcard_match(glob.as_bytes(), subject.as_bytes()))
+        } else {
+            false
+        };
+
+        name_matches
+            && self
+                .content_prefix
+                .as_ref()
+                .is_none_or(|prefix| read_contents && has_magic(path, prefix.as_bytes()))
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of
+/// characters and `?` for a single one.
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it currently covers
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, covered)) => {
+                    p = star + 1;
+                    t = covered + 1;
+                    backtrack = Some((star, covered + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serial, TempTree};
+
+    fn rules(tree: &TempTree, text: &str) -> anyhow::Result<Vec<CustomRule>> {
+        load_rules(&tree.file("rules.toml", text))
+    }
+
+    fn label_of<'a>(rules: &'a [CustomRule], path: &Path) -> Option<&'a str> {
+        rules
+            .iter()
+            .find(|rule| rule.matches(path, true))
+            .map(|rule| rule.label.as_str())
+    }
+
+    #[test]
+    fn files_get_the_label_of_the_first_matching_rule() {
+        let _serial = serial();
+        let tree = TempTree::new();
+        let rules = rules(
+            &tree,
+            r#"
+            [[rule]]
+            filename = "Notes.TXT"
+            label = "notes"
+
+            [[rule]]
+            extension = ".blend"
+            label = "3d"
+
+            [[rule]]
+            glob = "*/projects/*/draft-??.md"
+            label = "drafts"
+
+            [[rule]]
+            extension = "dat"
+            content_prefix = "SAVE"
+            label = "saves"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(label_of(&rules, &tree.file("notes.txt", "")), Some("notes"));
+        assert_eq!(label_of(&rules, &tree.file("scene.BLEND", "")), Some("3d"));
+        assert_eq!(
+            label_of(&rules, &tree.file("projects/book/draft-01.md", "")),
+            Some("drafts")
+        );
+        assert_eq!(
+            label_of(&rules, &tree.file("projects/book/draft-1.md", "")),
+            None
+        );
+        assert_eq!(
+            label_of(&rules, &tree.file("slot.dat", "SAVE\x01")),
+            Some("saves")
+        );
+        assert_eq!(label_of(&rules, &tree.file("other.dat", "DATA")), None);
+    }
+
+    #[test]
+    fn rules_need_exactly_one_pattern_and_a_contained_label() {
+        let tree = TempTree::new();
+        let error = rules(&tree, "[[rule]]\nlabel = \"x\"\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("exactly one of"));
+
+        let error = rules(
+            &tree,
+            "[[rule]]\nextension = \"a\"\nfilename = \"b\"\nlabel = \"x\"\n",
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains("exactly one of"));
+
+        let error = rules(&tree, "[[rule]]\nextension = \"a\"\nlabel = \"../x\"\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("invalid label"));
+    }
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(wildcard_match(b"*.log", b"app.log"));
+        assert!(wildcard_match(b"a*b*c", b"axxbyyc"));
+        assert!(wildcard_match(b"?x", b"ax"));
+        assert!(!wildcard_match(b"?x", b"x"));
+        assert!(!wildcard_match(b"*.log", b"app.logs"));
+    }
+}
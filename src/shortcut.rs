@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use crate::magic::read_prefix;
+
+/// Size of the fixed header that starts every shell link.
+const HEADER_SIZE: usize = 0x4c;
+
+// LinkFlags, MS-SHLLINK 2.1.1
+const HAS_LINK_TARGET_ID_LIST: u32 = 0x1;
+const HAS_LINK_INFO: u32 = 0x2;
+const HAS_NAME: u32 = 0x4;
+const HAS_RELATIVE_PATH: u32 = 0x8;
+const IS_UNICODE: u32 = 0x80;
+
+// LinkInfoFlags, MS-SHLLINK 2.3
+const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x1;
+const COMMON_NETWORK_RELATIVE_LINK: u32 = 0x2;
+
+/// Shortcuts are a few KiB at most, anything past this is not worth reading.
+const MAX_LINK_SIZE: usize = 64 * 1024;
+
+/// Resolves the target of a Windows `.lnk` shortcut by parsing the shell
+/// link format directly, so it works on any platform. Prefers the absolute
+/// local or network path and falls back to the relative path stored in the
+/// link, which is resolved against the shortcut's directory.
+pub fn read_link_target(path: &Path) -> Option<PathBuf> {
+    let data = read_prefix(path, MAX_LINK_SIZE);
+    if u32_at(&data, 0)? as usize != HEADER_SIZE {
+        return None;
+    }
+    let flags = u32_at(&data, 0x14)?;
+
+    let mut offset = HEADER_SIZE;
+    if flags & HAS_LINK_TARGET_ID_LIST != 0 {
+        offset += 2 + u16_at(&data, offset)? as usize;
+    }
+
+    if flags & HAS_LINK_INFO != 0 {
+        let info = data.get(offset..)?;
+        let info_size = u32_at(info, 0)? as usize;
+        if let Some(target) = link_info_target(info.get(..info_size)?) {
+            return Some(PathBuf::from(target));
+        }
+        offset += info_size;
+    }
+
+    // StringData: NAME_STRING, then RELATIVE_PATH, each a character count
+    // followed by UTF-16 or code page characters
+    let unicode = flags & IS_UNICODE != 0;
+    let char_size = if unicode { 2 } else { 1 };
+    if flags & HAS_NAME != 0 {
+        offset += 2 + u16_at(&data, offset)? as usize * char_size;
+    }
+    if flags & HAS_RELATIVE_PATH != 0 {
+        let count = u16_at(&data, offset)? as usize;
+        let chars = data.get(offset + 2..offset + 2 + count * char_size)?;
+        let relative = if unicode {
+            decode_utf16(chars)
+        } else {
+            String::from_utf8_lossy(chars).into_owned()
+        };
+        #[cfg(not(windows))]
+        let relative = relative.replace('\\', "/");
+        return Some(path.parent()?.join(relative));
+    }
+
+    None
+}
+
+/// Extracts the absolute target from a LinkInfo structure.
+fn link_info_target(info: &[u8]) -> Option<String> {
+    let header_size = u32_at(info, 4)? as usize;
+    let flags = u32_at(info, 8)?;
+    let has_unicode = header_size >= 0x24;
+
+    let suffix = if has_unicode {
+        wide_string_at(info, u32_at(info, 0x20)? as usize)
+    } else {
+        c_string_at(info, u32_at(info, 0x18)? as usize)
+    }
+    .unwrap_or_default();
+
+    if flags & VOLUME_ID_AND_LOCAL_BASE_PATH != 0 {
+        let base = if has_unicode {
+            wide_string_at(info, u32_at(info, 0x1c)? as usize)
+        } else {
+            c_string_at(info, u32_at(info, 0x10)? as usize)
+        }?;
+        return Some(base + &suffix);
+    }
+
+    if flags & COMMON_NETWORK_RELATIVE_LINK != 0 {
+        let network = info.get(u32_at(info, 0x14)? as usize..)?;
+        let share = c_string_at(network, u32_at(network, 8)? as usize)?;
+        if suffix.is_empty() {
+            return Some(share);
+        }
+        return Some(format!("{}\\{}", share, suffix));
+    }
+
+    None
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// A NUL-terminated string in the system code page. The code page isn't
+/// recorded, so this decodes lossily as UTF-8.
+fn c_string_at(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+fn wide_string_at(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.chunks_exact(2).position(|c| c == [0, 0])?;
+    Some(decode_utf16(&bytes[..len * 2]))
+}
+
+fn decode_utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tag_of, TempTree};
+
+    /// A shell link header with `flags`, followed by `body`.
+    fn link(flags: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..4].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        data[0x14..0x18].copy_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// A LinkInfo structure without the optional Unicode fields, with the
+    /// two strings following its header.
+    fn link_info(flags: u32, first: &[u8], suffix: &str) -> Vec<u8> {
+        const HEADER: u32 = 0x1c;
+        let suffix_offset = HEADER + first.len() as u32;
+        let size = suffix_offset + suffix.len() as u32 + 1;
+        let (base_offset, network_offset) = match flags {
+            VOLUME_ID_AND_LOCAL_BASE_PATH => (HEADER, 0),
+            _ => (0, HEADER),
+        };
+
+        let mut info = Vec::new();
+        for field in [
+            size,
+            HEADER,
+            flags,
+            0,
+            base_offset,
+            network_offset,
+            suffix_offset,
+        ] {
+            info.extend_from_slice(&field.to_le_bytes());
+        }
+        info.extend_from_slice(first);
+        info.extend_from_slice(suffix.as_bytes());
+        info.push(0);
+        info
+    }
+
+    #[test]
+    fn local_targets_are_read_from_the_link_info() {
+        let tree = TempTree::new();
+        let info = link_info(
+            VOLUME_ID_AND_LOCAL_BASE_PATH,
+            b"C:\\Users\\me\\notes.txt\0",
+            "",
+        );
+        let path = tree.file("notes.lnk", link(HAS_LINK_INFO, &info));
+        assert_eq!(
+            read_link_target(&path),
+            Some(PathBuf::from("C:\\Users\\me\\notes.txt"))
+        );
+        assert_eq!(tag_of(&path), "shortcut(-> C:\\Users\\me\\notes.txt)");
+    }
+
+    #[test]
+    fn network_targets_join_the_share_and_the_suffix() {
+        let tree = TempTree::new();
+        let mut network = Vec::new();
+        for field in [0u32, 0, 0x14, 0, 0] {
+            network.extend_from_slice(&field.to_le_bytes());
+        }
+        network.extend_from_slice(b"\\\\server\\share\0");
+        let info = link_info(COMMON_NETWORK_RELATIVE_LINK, &network, "docs\\file.txt");
+        let path = tree.file("file.lnk", link(HAS_LINK_INFO, &info));
+        assert_eq!(
+            read_link_target(&path),
+            Some(PathBuf::from("\\\\server\\share\\docs\\file.txt"))
+        );
+    }
+
+    #[test]
+    fn relative_targets_are_resolved_against_the_shortcut() {
+        let tree = TempTree::new();
+        let relative = "..\\docs\\a.txt".encode_utf16().collect::<Vec<_>>();
+        let mut body = (relative.len() as u16).to_le_bytes().to_vec();
+        body.extend(relative.iter().flat_map(|unit| unit.to_le_bytes()));
+        let path = tree.file("links/a.lnk", link(HAS_RELATIVE_PATH | IS_UNICODE, &body));
+
+        let expected = if cfg!(windows) {
+            "..\\docs\\a.txt"
+        } else {
+            "../docs/a.txt"
+        };
+        assert_eq!(
+            read_link_target(&path),
+            Some(tree.path().join("links").join(expected))
+        );
+    }
+
+    #[test]
+    fn other_files_have_no_target() {
+        let tree = TempTree::new();
+        assert_eq!(read_link_target(&tree.file("fake.lnk", "not a link")), None);
+        assert_eq!(read_link_target(&tree.file("empty.lnk", "")), None);
+    }
+}
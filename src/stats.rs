@@ -0,0 +1,620 @@
+use anyhow::Context;
+use serde::{Serialize, Serializer};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    config::{ExtensionOrder, SizeFormat},
+    dedup::DedupSavings,
+};
+
+#[derive(Clone, Default, Serialize)]
+pub struct CategoryStats {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Counts of the reported files, per category and overall.
+#[derive(Clone, Default, Serialize)]
+pub struct ScanStats {
+    pub categories: BTreeMap<String, CategoryStats>,
+    pub total: CategoryStats,
+    /// The same counts for each scanned root on its own, with
+    /// `--classification-stats-per-drive`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub drives: BTreeMap<PathBuf, ScanStats>,
+}
+
+impl ScanStats {
+    pub fn record(&mut self, category: &str, bytes: u64) {
+        let stats = self.categories.entry(category.to_owned()).or_default();
+        stats.files += 1;
+        stats.bytes += bytes;
+        self.total.files += 1;
+        self.total.bytes += bytes;
+    }
+
+    /// Adds the counts of another scan, e.g. of a drive scanned in
+    /// parallel.
+    pub fn merge(&mut self, other: ScanStats) {
+        for (category, stats) in other.categories {
+            let total = self.categories.entry(category).or_default();
+            total.files += stats.files;
+            total.bytes += stats.bytes;
+        }
+        self.total.files += other.total.files;
+        self.total.bytes += other.total.bytes;
+        for (root, stats) in other.drives {
+            self.drives.entry(root).or_default().merge(stats);
+        }
+    }
+
+    /// Adds the counts of a scan of `root`, keeping them apart for that
+    /// root as well.
+    pub fn merge_drive(&mut self, root: &Path, stats: ScanStats) {
+        self.drives
+            .entry(root.to_owned())
+            .or_default()
+            .merge(stats.clone());
+        self.merge(stats);
+    }
+
+    /// Prints a table per root, if kept apart, followed by the one of all
+    /// of them together.
+    pub fn print(&self, sizes: SizeFormat) {
+        for (root, stats) in &self.drives {
+            println!("{}", root.display());
+            stats.print(sizes);
+            println!();
+        }
+
+        let width = self
+            .categories
+            .keys()
+            .map(String::len)
+            .chain(["category".len()])
+            .max()
+            .unwrap_or_default();
+
+        let heading = match sizes {
+            SizeFormat::Bytes => "bytes",
+            _ => "size",
+        };
+        println!("{:<width$} {:>10} {:>16}", "category", "files", heading);
+        for (category, stats) in &self.categories {
+            println!(
+                "{:<width$} {:>10} {:>16}",
+                category,
+                stats.files,
+                sizes.amount(stats.bytes)
+            );
+        }
+        println!(
+            "{:<width$} {:>10} {:>16}",
+            "total",
+            self.total.files,
+            sizes.amount(self.total.bytes)
+        );
+    }
+}
+
+/// How the reported files of one category fared in `--magic-report`.
+#[derive(Default, Serialize)]
+pub struct Agreement {
+    /// No known signature to check the classification against.
+    pub extension_only: u64,
+    /// The file's signature fits its category.
+    pub confirmed: u64,
+    /// The file's signature belongs to another category.
+    pub mismatch: u64,
+}
+
+/// Tallies, per category, whether the signatures of the reported files
+/// agree with how they were classified, see `--magic-report`.
+#[derive(Default, Serialize)]
+pub struct MagicAgreement {
+    pub categories: BTreeMap<String, Agreement>,
+}
+
+impl MagicAgreement {
+    /// Counts a file of `category` whose signature allows `allowed`.
+    pub fn record(&mut self, category: &str, allowed: Option<&[&str]>) {
+        let agreement = self.categories.entry(category.to_owned()).or_default();
+        match allowed {
+            None => agreement.extension_only += 1,
+            Some(allowed) if allowed.contains(&category) => agreement.confirmed += 1,
+            Some(_) => agreement.mismatch += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: MagicAgreement) {
+        for (category, counts) in other.categories {
+            let total = self.categories.entry(category).or_default();
+            total.extension_only += counts.extension_only;
+            total.confirmed += counts.confirmed;
+            total.mismatch += counts.mismatch;
+        }
+    }
+
+    pub fn print(&self) {
+        let width = self
+            .categories
+            .keys()
+            .map(String::len)
+            .chain(["category".len()])
+            .max()
+            .unwrap_or_default();
+
+        println!(
+            "{:<width$} {:>14} {:>10} {:>10}",
+            "category", "extension-only", "confirmed", "mismatch"
+        );
+        for (category, counts) in &self.categories {
+            println!(
+                "{:<width$} {:>14} {:>10} {:>10}",
+                category, counts.extension_only, counts.confirmed, counts.mismatch
+            );
+        }
+    }
+}
+
+/// Counts of the walked files per lowercased extension, see
+/// `--by-extension`. Files without one are counted under the empty string.
+#[derive(Default)]
+pub struct ExtensionStats {
+    pub extensions: HashMap<String, CategoryStats>,
+}
+
+/// One line of the `--by-extension` report.
+#[derive(Serialize)]
+pub struct ExtensionRow<'a> {
+    pub extension: &'a str,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl ExtensionStats {
+    pub fn record(&mut self, extension: &str, bytes: u64) {
+        let stats = self.extensions.entry(extension.to_owned()).or_default();
+        stats.files += 1;
+        stats.bytes += bytes;
+    }
+
+    pub fn merge(&mut self, other: ExtensionStats) {
+        for (extension, stats) in other.extensions {
+            let total = self.extensions.entry(extension).or_default();
+            total.files += stats.files;
+            total.bytes += stats.bytes;
+        }
+    }
+
+    /// The tallies, largest first by `order` and by name among equals.
+    pub fn rows(&self, order: ExtensionOrder) -> Vec<ExtensionRow<'_>> {
+        let mut rows = self
+            .extensions
+            .iter()
+            .map(|(extension, stats)| ExtensionRow {
+                extension,
+                files: stats.files,
+                bytes: stats.bytes,
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|row| {
+            let key = match order {
+                ExtensionOrder::Count => (row.files, row.bytes),
+                ExtensionOrder::Size => (row.bytes, row.files),
+            };
+            (Reverse(key), row.extension)
+        });
+        rows
+    }
+
+    pub fn print(&self, order: ExtensionOrder, sizes: SizeFormat) {
+        let rows = self.rows(order);
+        let name = |row: &ExtensionRow| match row.extension {
+            "" => "(none)".to_owned(),
+            extension => format!(".{}", extension),
+        };
+        let width = rows
+            .iter()
+            .map(|row| name(row).len())
+            .chain(["extension".len()])
+            .max()
+            .unwrap_or_default();
+
+        let heading = match sizes {
+            SizeFormat::Bytes => "bytes",
+            _ => "size",
+        };
+        println!("{:<width$} {:>10} {:>16}", "extension", "files", heading);
+        for row in &rows {
+            println!(
+                "{:<width$} {:>10} {:>16}",
+                name(row),
+                row.files,
+                sizes.amount(row.bytes)
+            );
+        }
+    }
+}
+
+/// Sizes of the reported files per directory, see `--dir-sizes`. Each file
+/// is recorded in the directory holding it and only rolled up into the
+/// directories above once the scan is done.
+#[derive(Default)]
+pub struct DirSizes {
+    pub dirs: HashMap<PathBuf, CategoryStats>,
+}
+
+/// One line of the `--dir-sizes` report, counting everything below `path`.
+#[derive(Serialize)]
+pub struct DirSizeRow {
+    pub path: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl DirSizes {
+    pub fn record(&mut self, dir: &Path, bytes: u64) {
+        let stats = self.dirs.entry(dir.to_owned()).or_default();
+        stats.files += 1;
+        stats.bytes += bytes;
+    }
+
+    pub fn merge(&mut self, other: DirSizes) {
+        for (dir, stats) in other.dirs {
+            let total = self.dirs.entry(dir).or_default();
+            total.files += stats.files;
+            total.bytes += stats.bytes;
+        }
+    }
+
+    /// The `top` largest directories, each counting the files of all the
+    /// directories below it. Totals are rolled up as far as the scanned
+    /// root a directory lies in, not into the folders above it.
+    pub fn rows(&self, roots: &[PathBuf], top: usize) -> Vec<DirSizeRow> {
+        let mut totals = HashMap::<&Path, CategoryStats>::new();
+        for (dir, stats) in &self.dirs {
+            let root = roots
+                .iter()
+                .filter(|root| dir.starts_with(root))
+                .max_by_key(|root| root.components().count());
+            for ancestor in dir.ancestors() {
+                let total = totals.entry(ancestor).or_default();
+                total.files += stats.files;
+                total.bytes += stats.bytes;
+                if root.is_some_and(|root| ancestor == root) {
+                    break;
+                }
+            }
+        }
+
+        let mut rows = totals
+            .into_iter()
+            .map(|(path, stats)| DirSizeRow {
+                path: path.to_owned(),
+                files: stats.files,
+                bytes: stats.bytes,
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| (Reverse(a.bytes), &a.path).cmp(&(Reverse(b.bytes), &b.path)));
+        rows.truncate(top);
+        rows
+    }
+
+    pub fn print(&self, roots: &[PathBuf], top: usize, sizes: SizeFormat) {
+        let heading = match sizes {
+            SizeFormat::Bytes => "bytes",
+            _ => "size",
+        };
+        println!("{:>16} {:>10}  directory", heading, "files");
+        for row in self.rows(roots, top) {
+            println!(
+                "{:>16} {:>10}  {}",
+                sizes.amount(row.bytes),
+                row.files,
+                row.path.display()
+            );
+        }
+    }
+}
+
+/// Counts of what `--diff-dest` found in the destination.
+#[derive(Default, Serialize)]
+pub struct DestDiff {
+    pub create: u64,
+    pub identical: u64,
+    pub overwrite: u64,
+}
+
+impl DestDiff {
+    pub fn merge(&mut self, other: DestDiff) {
+        self.create += other.create;
+        self.identical += other.identical;
+        self.overwrite += other.overwrite;
+    }
+
+    pub fn print(&self) {
+        println!("would create    {:>10}", self.create);
+        println!("would skip      {:>10}", self.identical);
+        println!("would overwrite {:>10}", self.overwrite);
+    }
+}
+
+/// Work done by a run and the time its phases took, for the throughput
+/// printed at the end.
+///
+/// Phase times are summed over every thread, so with parallel drives they
+/// can add up to more than the run took.
+#[derive(Default, Serialize)]
+pub struct Throughput {
+    /// Files the walk came across, reported or not.
+    pub files: u64,
+    /// Source bytes read to hash them.
+    pub bytes_read: u64,
+    /// Bytes copied into the destination, before compression.
+    pub bytes_copied: u64,
+    #[serde(serialize_with = "seconds")]
+    pub walk: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub hash: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub copy: Duration,
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Throughput {
+    pub fn merge(&mut self, other: Throughput) {
+        self.files += other.files;
+        self.bytes_read += other.bytes_read;
+        self.bytes_copied += other.bytes_copied;
+        self.walk += other.walk;
+        self.hash += other.hash;
+        self.copy += other.copy;
+    }
+
+    /// Prints the rates over `elapsed`, the wall time of the whole run, to
+    /// stderr.
+    pub fn print(&self, elapsed: Duration, sizes: SizeFormat) {
+        for line in self.summary(elapsed, sizes) {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// The lines `print` writes. Phases that didn't happen are left out.
+    /// Amounts are in decimal megabytes unless `sizes` asks for
+    /// human-readable ones.
+    #[allow(clippy::cast_precision_loss)]
+    fn summary(&self, elapsed: Duration, sizes: SizeFormat) -> Vec<String> {
+        let mut line = format!(
+            "scanned {} files in {:.1}s ({:.0} files/s)",
+            self.files,
+            elapsed.as_secs_f64(),
+            per_second(self.files as f64, elapsed)
+        );
+        for (verb, bytes) in [("read", self.bytes_read), ("copied", self.bytes_copied)] {
+            if bytes == 0 {
+                continue;
+            }
+            if sizes == SizeFormat::Bytes {
+                line.push_str(&format!(
+                    ", {} {:.1} MB ({:.1} MB/s)",
+                    verb,
+                    megabytes(bytes),
+                    megabytes_per_second(bytes, elapsed)
+                ));
+            } else {
+                // a whole number of bytes per second is plenty for a rate
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let rate = per_second(bytes as f64, elapsed) as u64;
+                line.push_str(&format!(
+                    ", {} {} ({}/s)",
+                    verb,
+                    sizes.amount(bytes),
+                    sizes.amount(rate)
+                ));
+            }
+        }
+        let mut lines = vec![line];
+
+        let phases = [
+            ("walk", self.walk),
+            ("hash", self.hash),
+            ("copy", self.copy),
+        ]
+        .into_iter()
+        .filter(|(_, time)| !time.is_zero())
+        .map(|(phase, time)| format!("{} {:.1}s", phase, time.as_secs_f64()))
+        .collect::<Vec<_>>();
+        if !phases.is_empty() {
+            lines.push(format!("time spent: {}", phases.join(", ")));
+        }
+        lines
+    }
+}
+
+/// Everything `--stats-json` writes about a run. Times are in seconds.
+#[derive(Serialize)]
+pub struct RunStats<'a> {
+    /// False if the run was cancelled or used up `--max-runtime`.
+    pub complete: bool,
+    pub elapsed: f64,
+    /// Files written to the destination, streams included.
+    pub copied: usize,
+    /// Files that couldn't be read and were left out.
+    pub skipped: usize,
+    /// Files held open by another process, also counted as skipped.
+    pub locked: usize,
+    /// Counts of the reported files per category.
+    pub reported: &'a ScanStats,
+    /// Counts of every walked file per extension, most files first.
+    pub extensions: Vec<ExtensionRow<'a>>,
+    pub throughput: &'a Throughput,
+    /// What `--diff-dest` found, if it was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<&'a DestDiff>,
+    /// What `--dedupe-across-drives` saved, if it was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deduplicated: Option<DedupSavings>,
+}
+
+impl RunStats<'_> {
+    /// Writes the statistics to `path`, through a temporary file so a
+    /// reader never sees half of them.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?).with_context(|| {
+            format!("failed to write statistics `{}`", Path::new(&tmp).display())
+        })?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("failed to write statistics `{}`", path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / 1_000_000.0
+}
+
+/// Decimal megabytes per second moved in `elapsed`, 0 for no time at all.
+pub fn megabytes_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    per_second(megabytes(bytes), elapsed)
+}
+
+fn per_second(amount: f64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        amount / seconds
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_taken_over_the_whole_run() {
+        let throughput = Throughput {
+            files: 500,
+            bytes_read: 40_000_000,
+            bytes_copied: 10_000_000,
+            walk: Duration::from_millis(1500),
+            hash: Duration::ZERO,
+            copy: Duration::from_secs(2),
+        };
+        assert_eq!(
+            throughput.summary(Duration::from_secs(4), SizeFormat::Bytes),
+            [
+                "scanned 500 files in 4.0s (125 files/s), read 40.0 MB (10.0 MB/s), \
+                 copied 10.0 MB (2.5 MB/s)",
+                // no hashing took place
+                "time spent: walk 1.5s, copy 2.0s",
+            ]
+        );
+        assert!((megabytes_per_second(3_000_000, Duration::from_millis(1500)) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nothing_moved_in_no_time_has_no_rate() {
+        let throughput = Throughput::default();
+        assert_eq!(
+            throughput.summary(Duration::ZERO, SizeFormat::Bytes),
+            ["scanned 0 files in 0.0s (0 files/s)"]
+        );
+        assert_eq!(megabytes_per_second(1_000_000, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn agreement_of_each_drive_adds_up() {
+        let mut first = MagicAgreement::default();
+        first.record("databases", Some(&["databases", "chats"]));
+        first.record("databases", Some(&["photos"]));
+        let mut second = MagicAgreement::default();
+        second.record("databases", None);
+        second.record("photos", Some(&["photos"]));
+        first.merge(second);
+
+        let databases = &first.categories["databases"];
+        assert_eq!(
+            (
+                databases.extension_only,
+                databases.confirmed,
+                databases.mismatch
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(first.categories["photos"].confirmed, 1);
+    }
+
+    #[test]
+    fn directory_sizes_roll_up_as_far_as_their_root() {
+        let mut first = DirSizes::default();
+        first.record(Path::new("/home/me"), 1);
+        first.record(Path::new("/home/me/docs"), 10);
+        first.record(Path::new("/home/me/docs/tax"), 100);
+        let mut second = DirSizes::default();
+        second.record(Path::new("/home/me/docs/tax"), 200);
+        second.record(Path::new("/srv/data"), 50);
+        first.merge(second);
+
+        let rows = first
+            .rows(&[PathBuf::from("/home/me"), PathBuf::from("/srv")], 10)
+            .into_iter()
+            .map(|row| (row.path.display().to_string(), row.files, row.bytes))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                ("/home/me".to_owned(), 4, 311),
+                ("/home/me/docs".to_owned(), 3, 310),
+                ("/home/me/docs/tax".to_owned(), 2, 300),
+                ("/srv".to_owned(), 1, 50),
+                ("/srv/data".to_owned(), 1, 50),
+            ]
+        );
+        assert_eq!(first.rows(&[PathBuf::from("/home/me")], 2).len(), 2);
+    }
+
+    #[test]
+    fn drives_are_kept_apart_and_added_up() {
+        let mut c = ScanStats::default();
+        c.record("databases", 100);
+        let mut d = ScanStats::default();
+        d.record("databases", 50);
+        d.record("secrets", 5);
+        let mut total = ScanStats::default();
+        total.merge_drive(Path::new("C:\\"), c);
+        total.merge_drive(Path::new("D:\\"), d);
+
+        let files = |stats: &ScanStats, category: &str| {
+            stats
+                .categories
+                .get(category)
+                .map(|stats| (stats.files, stats.bytes))
+        };
+        let (c, d) = (
+            &total.drives[Path::new("C:\\")],
+            &total.drives[Path::new("D:\\")],
+        );
+        assert_eq!(files(c, "databases"), Some((1, 100)));
+        assert_eq!(files(c, "secrets"), None);
+        assert_eq!(files(d, "secrets"), Some((1, 5)));
+        assert_eq!(files(&total, "databases"), Some((2, 150)));
+        assert_eq!((total.total.files, total.total.bytes), (3, 155));
+        // the tables per drive don't nest any further
+        assert!(c.drives.is_empty() && d.drives.is_empty());
+    }
+}
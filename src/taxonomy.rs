@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    platform::SpecialFileType, AppKind, AppSecretKind, ArchiveFileType, CaptureFormat, ChatApp,
+    ConfigurationFileType, CrashKind, CredentialCacheType, DataScienceFileType, DatabaseFileType,
+    DirectoryClassification, DiskImageKind, DocumentFileType, DownloadKind, EbookFormat,
+    EncryptedKind, EntryClassification, FileClassification, GameDataKind, HistoryFileType,
+    IacFormat, InfraSecretKind, InstallerKind, MediaFormat, MobileKind, MultiPartArchive,
+    MultiPartFormat, PasswordManager, Pkcs7Kind, PlistFormat, RegistryHive, SecretFileType,
+    SpecialKind, SpreadsheetFileType, SubtitleFormat, VcsFileType, VersionControlSystem,
+    WebArchiveKind, CATEGORIES,
+};
+
+/// Builds the classification a table entry stands for.
+pub type Constructor = fn() -> FileClassification;
+
+/// Files recognized by their exact lowercased name alone.
+pub const FILE_NAMES: &[(&[&str], Constructor)] = &[
+    (&[".env"], || {
+        FileClassification::Secret(SecretFileType::Env)
+    }),
+    (&["terraform.tfstate.backup"], || {
+        FileClassification::Secret(SecretFileType::Infrastructure(
+            InfraSecretKind::TerraformState,
+        ))
+    }),
+    (&["id_rsa", "id_dsa", "id_ecdsa", "id_ed25519"], || {
+        FileClassification::Secret(SecretFileType::Infrastructure(InfraSecretKind::PrivateKey))
+    }),
+    (&["kubeconfig"], || {
+        FileClassification::Secret(SecretFileType::Infrastructure(InfraSecretKind::Kubeconfig))
+    }),
+    (&[".bash_history"], || {
+        FileClassification::Secret(SecretFileType::History(HistoryFileType::Bash))
+    }),
+    (&[".zsh_history"], || {
+        FileClassification::Secret(SecretFileType::History(HistoryFileType::Zsh))
+    }),
+    (&[".python_history"], || {
+        FileClassification::Secret(SecretFileType::History(HistoryFileType::Python))
+    }),
+    (&[".mysql_history"], || {
+        FileClassification::Secret(SecretFileType::History(HistoryFileType::Mysql))
+    }),
+    (&[".psql_history"], || {
+        FileClassification::Secret(SecretFileType::History(HistoryFileType::Psql))
+    }),
+    (&[".netrc", "_netrc"], || {
+        FileClassification::Secret(SecretFileType::CredentialCache(CredentialCacheType::Netrc))
+    }),
+    (&["wp-config.php"], || {
+        FileClassification::Secret(SecretFileType::AppSecret(AppSecretKind::WordPress))
+    }),
+    (&[".htpasswd"], || {
+        FileClassification::Secret(SecretFileType::AppSecret(AppSecretKind::Htpasswd))
+    }),
+];
+
+/// Files recognized by their lowercased extension alone.
+pub const EXTENSIONS: &[(&[&str], Constructor)] = &[
+    (
+        &[
+            "xlw", "xlr", "xls", "xlsx", "xlsm", "xlsl", "xlsb", "xltx", "xltm", "xlam", "xla",
+        ],
+        || FileClassification::Spreadsheet(SpreadsheetFileType::Excel),
+    ),
+    (
+        &["ppt", "pptx", "pptm", "potx", "potm", "ppsx", "ppsm"],
+        || FileClassification::Document(DocumentFileType::PowerPoint),
+    ),
+    (&["txt", "log"], || {
+        FileClassification::Document(DocumentFileType::Text)
+    }),
+    (&["pdf"], || {
+        FileClassification::Document(DocumentFileType::Pdf)
+    }),
+    (
+        &[
+            "rtf", "odt", "xps", "wps", "dotx", "dotm", "docx", "docm", "doc",
+        ],
+        || FileClassification::Document(DocumentFileType::Word),
+    ),
+    (&["db", "dump"], || {
+        FileClassification::Database(DatabaseFileType::Db)
+    }),
+    (&["sqlite", "sqlite3"], || {
+        FileClassification::Database(DatabaseFileType::Sqlite)
+    }),
+    (&["sql", "mysql", "pgsql"], || {
+        FileClassification::Database(DatabaseFileType::Sql)
+    }),
+    (&["pdb"], || {
+        FileClassification::Database(DatabaseFileType::Pdb)
+    }),
+    (&["yaml"], || {
+        FileClassification::Configuration(ConfigurationFileType::Yaml)
+    }),
+    (&["opvault", "1pif"], || {
+        FileClassification::Secret(SecretFileType::PasswordDatabase(
+            PasswordManager::OnePassword,
+        ))
+    }),
+    (&["ini"], || {
+        FileClassification::Configuration(ConfigurationFileType::Ini)
+    }),
+    (&["tfstate"], || {
+        FileClassification::Secret(SecretFileType::Infrastructure(
+            InfraSecretKind::TerraformState,
+        ))
+    }),
+    (&["tfvars"], || {
+        FileClassification::Secret(SecretFileType::Infrastructure(
+            InfraSecretKind::TerraformVars,
+        ))
+    }),
+    (&["tf", "tofu"], || {
+        FileClassification::InfrastructureCode(IacFormat::Terraform)
+    }),
+    (&["pem", "crt", "cer", "der"], || {
+        FileClassification::Certificate
+    }),
+    (&["p7b", "p7c"], || {
+        FileClassification::Pkcs7(Pkcs7Kind::Certificates)
+    }),
+    (&["p7s"], || FileClassification::Pkcs7(Pkcs7Kind::Signature)),
+    (&["p7m"], || FileClassification::Pkcs7(Pkcs7Kind::Message)),
+    (&["zip"], || {
+        FileClassification::Archive(ArchiveFileType::Zip)
+    }),
+    (&["gz", "tgz"], || {
+        FileClassification::Archive(ArchiveFileType::Gzip)
+    }),
+    (&["tar"], || {
+        FileClassification::Archive(ArchiveFileType::Tar)
+    }),
+    (&["rar"], || {
+        FileClassification::Archive(ArchiveFileType::Rar)
+    }),
+    (&["7z"], || {
+        FileClassification::Archive(ArchiveFileType::SevenZip)
+    }),
+    (&["vhd"], || {
+        FileClassification::DiskImage(DiskImageKind::Vhd)
+    }),
+    (&["vhdx"], || {
+        FileClassification::DiskImage(DiskImageKind::Vhdx)
+    }),
+    (&["vmdk"], || {
+        FileClassification::DiskImage(DiskImageKind::Vmdk)
+    }),
+    (&["qcow2"], || {
+        FileClassification::DiskImage(DiskImageKind::Qcow2)
+    }),
+    (&["vdi"], || {
+        FileClassification::DiskImage(DiskImageKind::Vdi)
+    }),
+    (&["img"], || {
+        FileClassification::DiskImage(DiskImageKind::Raw)
+    }),
+    (&["msi"], || {
+        FileClassification::Installer(InstallerKind::Msi)
+    }),
+    (&["exe"], || {
+        FileClassification::Installer(InstallerKind::Exe)
+    }),
+    (&["deb"], || {
+        FileClassification::Installer(InstallerKind::Deb)
+    }),
+    (&["rpm"], || {
+        FileClassification::Installer(InstallerKind::Rpm)
+    }),
+    (&["pkg"], || {
+        FileClassification::Installer(InstallerKind::Pkg)
+    }),
+    (&["dmg"], || {
+        FileClassification::Installer(InstallerKind::Dmg)
+    }),
+    (&["appimage"], || {
+        FileClassification::Installer(InstallerKind::AppImage)
+    }),
+    (&["flatpak"], || {
+        FileClassification::Installer(InstallerKind::Flatpak)
+    }),
+    (&["snap"], || {
+        FileClassification::Installer(InstallerKind::Snap)
+    }),
+    (&["ipynb"], || {
+        FileClassification::DataScience(DataScienceFileType::Notebook)
+    }),
+    (&["parquet"], || {
+        FileClassification::DataScience(DataScienceFileType::Parquet)
+    }),
+    (&["h5", "hdf5"], || {
+        FileClassification::DataScience(DataScienceFileType::Hdf5)
+    }),
+    (&["feather"], || {
+        FileClassification::DataScience(DataScienceFileType::Feather)
+    }),
+    (&["npy", "npz"], || {
+        FileClassification::DataScience(DataScienceFileType::Numpy)
+    }),
+    (&["mat"], || {
+        FileClassification::DataScience(DataScienceFileType::Matlab)
+    }),
+    (&["rdata", "rds"], || {
+        FileClassification::DataScience(DataScienceFileType::R)
+    }),
+    (
+        &["part", "crdownload", "download", "!ut", "partial"],
+        || FileClassification::Download(DownloadKind::Partial),
+    ),
+    (&["warc"], || {
+        FileClassification::WebArchive(WebArchiveKind::Warc { compressed: false })
+    }),
+    (&["har"], || {
+        FileClassification::WebArchive(WebArchiveKind::Har)
+    }),
+    (&["maff"], || {
+        FileClassification::WebArchive(WebArchiveKind::Maff)
+    }),
+    (&["mhtml", "mht"], || {
+        FileClassification::WebArchive(WebArchiveKind::Mhtml)
+    }),
+    (&["webarchive"], || {
+        FileClassification::WebArchive(WebArchiveKind::WebArchive)
+    }),
+    (&["wer"], || {
+        FileClassification::CrashDump(CrashKind::WerReport)
+    }),
+    (&["crash", "ips"], || {
+        FileClassification::CrashDump(CrashKind::CrashLog)
+    }),
+    (&["ab"], || {
+        FileClassification::MobileBackup(MobileKind::AndroidBackup)
+    }),
+    (&["srt"], || {
+        FileClassification::Subtitle(SubtitleFormat::Srt)
+    }),
+    (&["ass", "ssa"], || {
+        FileClassification::Subtitle(SubtitleFormat::SubStationAlpha)
+    }),
+    (&["vtt"], || {
+        FileClassification::Subtitle(SubtitleFormat::WebVtt)
+    }),
+    (&["sub"], || {
+        FileClassification::Subtitle(SubtitleFormat::Sub)
+    }),
+    (&["epub"], || FileClassification::Ebook(EbookFormat::Epub)),
+    (&["mobi", "azw", "azw3"], || {
+        FileClassification::Ebook(EbookFormat::Mobi)
+    }),
+    (&["fb2"], || {
+        FileClassification::Ebook(EbookFormat::FictionBook)
+    }),
+    (&["djvu"], || FileClassification::Ebook(EbookFormat::Djvu)),
+    (&["sav", "save"], || {
+        FileClassification::GameData(GameDataKind::Save)
+    }),
+];
+
+/// Types that take more than a name to recognize, like a look at the
+/// contents or where the file sits, described for `--list-categories`.
+/// Types whose tag varies with the file show an example.
+const OTHER_RULES: &[(Constructor, &str)] = &[
+    (
+        || FileClassification::Secret(SecretFileType::AppSecret(AppSecretKind::Django)),
+        "`settings.py` next to `wsgi.py`, `asgi.py` or `urls.py`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::AppSecret(AppSecretKind::Rails)),
+        "`database.yml`, `secrets.yml`, `master.key`, `credentials.yml.enc` in `config`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::AppSecret(AppSecretKind::AspNet)),
+        "`appsettings.json`, `appsettings.<environment>.json`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::Infrastructure(InfraSecretKind::Kubeconfig)),
+        "`config` in `.kube`",
+    ),
+    (
+        || {
+            FileClassification::Secret(SecretFileType::Infrastructure(
+                InfraSecretKind::AnsibleVault,
+            ))
+        },
+        "YAML and `vault` files starting with `$ANSIBLE_VAULT;`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::Infrastructure(InfraSecretKind::PrivateKey)),
+        "`.pem`, `.key` holding a PEM private key",
+    ),
+    (
+        || FileClassification::PublicKey,
+        "`.pub` starting with an SSH key type like `ssh-rsa` or `ssh-ed25519`, or any `.pub` \
+         under `--classify-only`",
+    ),
+    (
+        || FileClassification::GeotaggedPhoto,
+        "images with GPS coordinates in their EXIF metadata, with `--inspect-exif`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::CredentialCache(CredentialCacheType::Aws)),
+        "`credentials` in `.aws`",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::PasswordDatabase(PasswordManager::KeePass)),
+        "`.kdbx`, `.kdb` with the KeePass signature",
+    ),
+    (
+        || {
+            FileClassification::Secret(SecretFileType::PasswordDatabase(
+                PasswordManager::Bitwarden,
+            ))
+        },
+        "`.json` Bitwarden exports",
+    ),
+    (
+        || FileClassification::Secret(SecretFileType::PasswordDatabase(PasswordManager::LastPass)),
+        "`.csv` LastPass exports",
+    ),
+    (
+        || FileClassification::Spreadsheet(SpreadsheetFileType::Csv(',')),
+        "`.csv`, `.prn`, tagged with the most frequent separator",
+    ),
+    (
+        || FileClassification::Spreadsheet(SpreadsheetFileType::CsvUnknown),
+        "`.csv`, `.prn` under `--classify-only`",
+    ),
+    (
+        || FileClassification::Configuration(ConfigurationFileType::Json),
+        "`.json`",
+    ),
+    (
+        || FileClassification::Configuration(ConfigurationFileType::Plist(PlistFormat::Binary)),
+        "`.plist` starting with `bplist00`",
+    ),
+    (
+        || FileClassification::Configuration(ConfigurationFileType::Plist(PlistFormat::Xml)),
+        "other `.plist`",
+    ),
+    (
+        || FileClassification::Configuration(ConfigurationFileType::Plist(PlistFormat::Unknown)),
+        "`.plist` under `--classify-only`",
+    ),
+    (
+        || {
+            FileClassification::Configuration(ConfigurationFileType::RegistryExport {
+                credentials: false,
+            })
+        },
+        "`.reg` starting with a `regedit` header, or any `.reg` under `--classify-only`",
+    ),
+    (
+        || {
+            FileClassification::Configuration(ConfigurationFileType::RegistryExport {
+                credentials: true,
+            })
+        },
+        "`.reg` exports with values named like passwords, tokens or product keys",
+    ),
+    (
+        || {
+            FileClassification::Archive(ArchiveFileType::MultiPart(MultiPartArchive {
+                format: MultiPartFormat::Rar,
+                index: 1,
+            }))
+        },
+        "`.partN.rar`",
+    ),
+    (
+        || {
+            FileClassification::Archive(ArchiveFileType::MultiPart(MultiPartArchive {
+                format: MultiPartFormat::SevenZip,
+                index: 1,
+            }))
+        },
+        "`.7z.NNN`",
+    ),
+    (
+        || {
+            FileClassification::DiskImage(DiskImageKind::Optical {
+                label: None,
+                installer: false,
+            })
+        },
+        "`.iso`, tagged with its volume label and whether that is OS install media",
+    ),
+    (
+        || FileClassification::GameData(GameDataKind::Config),
+        "`.ini`, `.cfg`, `.xml`, `.json` in `My Games`, `Saved Games` or `AppData\\LocalLow`",
+    ),
+    (
+        || FileClassification::GameData(GameDataKind::Save),
+        "anything else in those folders",
+    ),
+    (
+        || FileClassification::GameData(GameDataKind::SteamUserData),
+        "anything below `Steam\\userdata`",
+    ),
+    (
+        || FileClassification::ApplicationConfig(AppKind::Thunderbird),
+        "settings, address books and mail folders of Thunderbird profiles, `.msf`",
+    ),
+    (
+        || FileClassification::ApplicationConfig(AppKind::Outlook),
+        "`.pst`, `.ost`, `Microsoft\\Signatures`",
+    ),
+    (
+        || FileClassification::ChatData(ChatApp::WhatsApp { encrypted: false }),
+        "`msgstore.db`, `wa.db`",
+    ),
+    (
+        || FileClassification::ChatData(ChatApp::WhatsApp { encrypted: true }),
+        "`msgstore*.db.cryptNN`, `wa.db.cryptNN`",
+    ),
+    (
+        || FileClassification::ChatData(ChatApp::Signal),
+        "`signal.sqlite`, `signal-*.backup`, `Signal\\sql\\db.sqlite`",
+    ),
+    (
+        || FileClassification::ChatData(ChatApp::Telegram),
+        "`cache4.db`, `Telegram Desktop\\tdata` without its caches",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::User),
+        "`NTUSER.DAT`",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::UserClasses),
+        "`UsrClass.dat`",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::System),
+        "`SYSTEM` in `config` or with the `regf` signature",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::Software),
+        "`SOFTWARE`, likewise",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::Sam),
+        "`SAM`, likewise",
+    ),
+    (
+        || FileClassification::RegistryHive(RegistryHive::Security),
+        "`SECURITY`, likewise",
+    ),
+    (
+        || FileClassification::Download(DownloadKind::Torrent),
+        "`.torrent` holding bencoded metadata",
+    ),
+    (
+        || FileClassification::WebArchive(WebArchiveKind::Warc { compressed: true }),
+        "`.warc.gz`",
+    ),
+    (
+        || FileClassification::CrashDump(CrashKind::CoreDump),
+        "`core`, `core.<pid>` with the ELF signature",
+    ),
+    (
+        || FileClassification::CrashDump(CrashKind::Minidump),
+        "`.dmp` with the `MDMP` signature",
+    ),
+    (
+        || FileClassification::NetworkCapture(CaptureFormat::Pcap),
+        "`.pcap`, `.pcapng`, `.cap` with a libpcap signature, or `.pcap` under `--classify-only`",
+    ),
+    (
+        || FileClassification::NetworkCapture(CaptureFormat::Pcapng),
+        "`.pcap`, `.pcapng`, `.cap` starting with a pcapng section header, or `.pcapng` under \
+         `--classify-only`",
+    ),
+    (
+        || FileClassification::CorruptMedia(MediaFormat::Jpeg),
+        "`.jpg`, `.jpeg`, `.jpe` without an end marker, with `--check-media`",
+    ),
+    (
+        || FileClassification::CorruptMedia(MediaFormat::Mp4),
+        "`.mp4`, `.m4v`, `.mov` with a box running past the end or no `moov` box, with \
+         `--check-media`",
+    ),
+    (
+        || FileClassification::CorruptMedia(MediaFormat::Matroska),
+        "`.mkv`, `.webm` shorter than their segment claims, with `--check-media`",
+    ),
+    (
+        || FileClassification::Encrypted(EncryptedKind::Pgp { armored: false }),
+        "`.gpg`, `.pgp` starting with an encrypted packet",
+    ),
+    (
+        || FileClassification::Encrypted(EncryptedKind::Pgp { armored: true }),
+        "`.gpg`, `.pgp`, `.asc`, `.age` with `-----BEGIN PGP MESSAGE-----`",
+    ),
+    (
+        || FileClassification::Encrypted(EncryptedKind::Age { armored: false }),
+        "`.age`, `.gpg`, `.pgp`, `.asc` starting with `age-encryption.org/v1`",
+    ),
+    (
+        || FileClassification::Encrypted(EncryptedKind::Age { armored: true }),
+        "`.age`, `.gpg`, `.pgp`, `.asc` with `-----BEGIN AGE ENCRYPTED FILE-----`",
+    ),
+    (
+        || FileClassification::Encrypted(EncryptedKind::Unknown),
+        "`.gpg`, `.pgp`, `.age` in neither format, and `.asc` under `--classify-only`",
+    ),
+    (
+        || FileClassification::MobileBackup(MobileKind::IosManifest),
+        "`Manifest.plist`, and `Manifest.db`, `Manifest.mbdb`, `Info.plist`, `Status.plist` next to it",
+    ),
+    (
+        || FileClassification::MobileBackup(MobileKind::IosData),
+        "files named by their hash in iOS backups",
+    ),
+    (
+        || FileClassification::VersionControl(VcsFileType::Submodules(vec!["path".to_owned()])),
+        "`.gitmodules`, tagged with the submodule paths",
+    ),
+    (
+        || {
+            FileClassification::VersionControl(VcsFileType::Submodule {
+                superproject: "path".into(),
+            })
+        },
+        "`.git` files of submodule checkouts",
+    ),
+    (
+        || FileClassification::VersionControl(VcsFileType::Worktree),
+        "`.git` files of linked worktrees",
+    ),
+    (
+        || FileClassification::VersionControl(VcsFileType::GitLink),
+        "other `.git` files",
+    ),
+    (
+        || FileClassification::Shortcut {
+            target: Some("path".into()),
+        },
+        "`.lnk`, tagged with the target if it could be read",
+    ),
+];
+
+/// Directories reported as a whole, described for `--list-categories`.
+const DIRECTORIES: &[(DirectoryClassification, &str)] = &[
+    (
+        DirectoryClassification::VersionControl(VersionControlSystem::Git),
+        "`.git`",
+    ),
+    (
+        DirectoryClassification::VersionControl(VersionControlSystem::Svn),
+        "`.svn`",
+    ),
+    (DirectoryClassification::AppBundle, "macOS `.app` bundles"),
+    (
+        DirectoryClassification::PasswordDatabase(PasswordManager::OnePassword),
+        "`.opvault`, `.1pif` bundles",
+    ),
+];
+
+/// Entries reported but never copied, described for `--list-categories`.
+const SPECIAL: &[(SpecialKind, &str)] = &[
+    (
+        SpecialKind::Lock,
+        "`.lock`, except those of package managers like `Cargo.lock`",
+    ),
+    (SpecialKind::Pid, "`.pid`"),
+    (SpecialKind::File(SpecialFileType::Socket), "Unix sockets"),
+    (SpecialKind::File(SpecialFileType::Fifo), "named pipes"),
+    (
+        SpecialKind::File(SpecialFileType::BlockDevice),
+        "block devices",
+    ),
+    (
+        SpecialKind::File(SpecialFileType::CharDevice),
+        "character devices",
+    ),
+];
+
+/// Looks `key` up in one of the name tables.
+pub fn lookup(table: &[(&[&str], Constructor)], key: &str) -> Option<FileClassification> {
+    table
+        .iter()
+        .find(|(keys, _)| keys.contains(&key))
+        .map(|(_, class)| class())
+}
+
+/// Prints every tag the classifier can produce, grouped by category, with
+/// what it matches. Custom rules are left out as they depend on the rules
+/// file.
+pub fn print_categories() {
+    let sections = listing();
+    let width = sections
+        .iter()
+        .flat_map(|(_, tags)| tags)
+        .map(|(tag, _)| tag.len())
+        .max()
+        .unwrap_or_default();
+    for (heading, tags) in sections {
+        println!("{}", heading);
+        for (tag, matches) in tags {
+            println!("  {:<width$}  {}", tag, matches);
+        }
+    }
+}
+
+/// What `print_categories` prints: a heading for each category in the
+/// order they are documented in, not alphabetically, followed by the
+/// directories and special entries, each with its tags and what they
+/// match.
+fn listing() -> Vec<(&'static str, Vec<(String, String)>)> {
+    let mut by_category: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    let tables = FILE_NAMES
+        .iter()
+        .map(|(names, class)| (class(), quoted(names, "")))
+        .chain(
+            EXTENSIONS
+                .iter()
+                .map(|(extensions, class)| (class(), quoted(extensions, "."))),
+        )
+        .chain(
+            OTHER_RULES
+                .iter()
+                .map(|(class, matches)| (class(), (*matches).to_owned())),
+        );
+    for (class, matches) in tables {
+        let Some(category) = class.category() else {
+            continue;
+        };
+        by_category
+            .entry(category.to_owned())
+            .or_default()
+            .push((class.to_string(), matches));
+    }
+
+    let directories = DIRECTORIES
+        .iter()
+        .map(|(class, matches)| {
+            (
+                EntryClassification::Dir(class.clone()).to_string(),
+                (*matches).to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let special = SPECIAL
+        .iter()
+        .map(|(kind, matches)| (kind.to_string(), (*matches).to_owned()))
+        .collect::<Vec<_>>();
+
+    let mut sections = CATEGORIES
+        .iter()
+        .filter_map(|category| Some((*category, by_category.remove(*category)?)))
+        .collect::<Vec<_>>();
+    sections.push(("directories", directories));
+    sections.push(("special (not copied)", special));
+    sections
+}
+
+fn quoted(names: &[&str], prefix: &str) -> String {
+    names
+        .iter()
+        .map(|name| format!("`{}{}`", prefix, name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serial, tag_of, TempTree};
+
+    #[test]
+    fn every_category_is_listed_in_order() {
+        let sections = listing();
+        let headings = sections
+            .iter()
+            .map(|(heading, _)| *heading)
+            .collect::<Vec<_>>();
+        assert_eq!(headings[..CATEGORIES.len()], *CATEGORIES);
+        assert_eq!(
+            headings[CATEGORIES.len()..],
+            ["directories", "special (not copied)"]
+        );
+        assert!(sections.iter().all(|(_, tags)| !tags.is_empty()));
+    }
+
+    #[test]
+    fn names_in_the_tables_classify_as_listed() {
+        let _serial = serial();
+        let tree = TempTree::new();
+        let mut wrong = Vec::new();
+        let names = FILE_NAMES
+            .iter()
+            .flat_map(|(names, class)| names.iter().map(|name| ((*name).to_owned(), class())))
+            .chain(EXTENSIONS.iter().flat_map(|(extensions, class)| {
+                extensions
+                    .iter()
+                    .map(|extension| (format!("file.{extension}"), class()))
+            }));
+        for (i, (name, class)) in names.enumerate() {
+            let tag = tag_of(&tree.file(&format!("{i}/{name}"), ""));
+            if tag != class.to_string() {
+                wrong.push(format!("{name}: {tag} instead of {class}"));
+            }
+        }
+        assert!(wrong.is_empty(), "{wrong:#?}");
+    }
+}
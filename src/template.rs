@@ -0,0 +1,176 @@
+use anyhow::bail;
+
+/// A value that can be substituted into an output line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Path,
+    /// The classification tag, e.g. `zip` or `secret(netrc)`.
+    Class,
+    /// `#`, or `#!` for high-sensitivity entries.
+    Marker,
+    /// The parenthesized notes like ` (empty)`, with their leading spaces.
+    Tags,
+    Category,
+    /// Size in bytes.
+    Size,
+    /// Modification time as seconds since the Unix epoch.
+    Mtime,
+}
+
+impl Field {
+    const ALL: &[(&str, Field)] = &[
+        ("path", Field::Path),
+        ("class", Field::Class),
+        ("marker", Field::Marker),
+        ("tags", Field::Tags),
+        ("category", Field::Category),
+        ("size", Field::Size),
+        ("mtime", Field::Mtime),
+    ];
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A `--template` output line such as `{size}\t{path}`.
+///
+/// `{name}` is replaced by the named [`Field`], `{{` and `}}` stand for
+/// literal braces and `\t` and `\n` for a tab and a newline.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '\\' if chars.peek() == Some(&'t') => {
+                    chars.next();
+                    literal.push('\t');
+                }
+                '\\' if chars.peek() == Some(&'n') => {
+                    chars.next();
+                    literal.push('\n');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("unclosed `{{` in template"),
+                        }
+                    }
+                    let Some(&(_, field)) = Field::ALL.iter().find(|(known, _)| *known == name)
+                    else {
+                        let known: Vec<_> = Field::ALL.iter().map(|(name, _)| *name).collect();
+                        bail!(
+                            "unknown placeholder `{{{}}}` in template, expected one of: {}",
+                            name,
+                            known.join(", ")
+                        );
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => bail!("unmatched `}}` in template, write `}}}}` for a literal brace"),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Fills in the template, asking `value` for each placeholder.
+    pub fn render(&self, mut value: impl FnMut(Field) -> String) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => line.push_str(text),
+                Part::Field(field) => line.push_str(&value(*field)),
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders `template` for a made-up entry.
+    fn render(template: &str) -> anyhow::Result<String> {
+        Ok(Template::parse(template)?.render(|field| {
+            match field {
+                Field::Path => "/home/me/.env",
+                Field::Class => "dotenv",
+                Field::Marker => "#!",
+                Field::Tags => " (empty)",
+                Field::Category => "secrets",
+                Field::Size => "0",
+                Field::Mtime => "1700000000",
+            }
+            .to_owned()
+        }))
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(render("{size}\\t{path}").unwrap(), "0\t/home/me/.env");
+        assert_eq!(
+            render("{path} {marker} {class}{tags}").unwrap(),
+            "/home/me/.env #! dotenv (empty)"
+        );
+        assert_eq!(
+            render("[{category}] {mtime}\\n").unwrap(),
+            "[secrets] 1700000000\n"
+        );
+        assert_eq!(render("no placeholders").unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{class}}}").unwrap(), "{dotenv}");
+        assert_eq!(render("\\x{{").unwrap(), "\\x{");
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        let error = render("{owner}").unwrap_err().to_string();
+        assert!(
+            error.starts_with("unknown placeholder `{owner}`"),
+            "{}",
+            error
+        );
+        assert!(error.ends_with("path, class, marker, tags, category, size, mtime"));
+        assert!(render("{path")
+            .unwrap_err()
+            .to_string()
+            .contains("unclosed"));
+        assert!(render("path}")
+            .unwrap_err()
+            .to_string()
+            .contains("unmatched"));
+    }
+}
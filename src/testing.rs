@@ -0,0 +1,405 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    fs,
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::SystemTime,
+};
+use walkdir::{DirEntry, WalkDir};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{
+    cache::ClassifyCache, classify_entry, config::ScanOptions, progress::Progress,
+    scan_roots_parallel, EntryClassification, FileClassification, ScanState,
+};
+
+/// Held by tests that change or depend on process-wide state, like the
+/// cancellation flag, so they don't run at the same time.
+pub fn serial() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Scans `config.roots` without a cache or progress bar, returning what
+/// the run accumulated.
+pub fn scan(config: &ScanOptions) -> ScanState {
+    let mut state = ScanState::new(config);
+    scan_roots_parallel(
+        &config.roots,
+        config,
+        &ClassifyCache::disabled(),
+        &Progress::new(false, None),
+        &mut state,
+    )
+    .expect("the scan succeeds");
+    state
+}
+
+/// A directory below the system's temporary folder, removed with everything
+/// in it when dropped.
+pub struct TempTree {
+    root: PathBuf,
+}
+
+impl TempTree {
+    pub fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let root = std::env::temp_dir().join(format!(
+            "backup-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&root).expect("failed to create the test directory");
+
+        // the walk compares against resolved paths, /tmp is a link on macOS
+        Self {
+            root: root.canonicalize().unwrap_or(root),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Writes `contents` to `name` below the root, creating its parents.
+    pub fn file(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create the test directory");
+        }
+        fs::write(&path, contents).expect("failed to write the test file");
+        path
+    }
+
+    /// Writes a zip archive to `name` below the root holding an entry with
+    /// some text for each of `entries`.
+    pub fn zip(&self, name: &str, entries: &[&str]) -> PathBuf {
+        let path = self.file(name, "");
+        let mut zip = ZipWriter::new(fs::File::create(&path).expect("failed to create the zip"));
+        for entry in entries {
+            zip.start_file(*entry, SimpleFileOptions::default())
+                .and_then(|()| Ok(zip.write_all(entry.as_bytes())?))
+                .expect("failed to write the zip");
+        }
+        zip.finish().expect("failed to write the zip");
+        path
+    }
+
+    /// Creates the directory `name` below the root.
+    pub fn dir(&self, name: &str) -> PathBuf {
+        let path = self.root.join(name);
+        fs::create_dir_all(&path).expect("failed to create the test directory");
+        path
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Sets the modification time of `path`.
+pub fn set_mtime(path: &Path, mtime: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(mtime))
+        .expect("failed to set the modification time");
+}
+
+/// The walk's entry for `path` itself.
+pub fn entry(path: &Path) -> DirEntry {
+    WalkDir::new(path)
+        .into_iter()
+        .next()
+        .expect("the walk yields its root")
+        .expect("the test file is readable")
+}
+
+/// The tag `path` is classified with by default, empty for regular files.
+pub fn tag_of(path: &Path) -> String {
+    classify(path).to_string()
+}
+
+/// Classifies `path` the way the scan does with the default options.
+pub fn classify(path: &Path) -> EntryClassification {
+    classify_with(path, &ScanOptions::default())
+}
+
+/// Classifies `path` the way the scan does with `config`.
+pub fn classify_with(path: &Path, config: &ScanOptions) -> EntryClassification {
+    classify_entry(&entry(path), config)
+}
+
+/// The tag `path` is classified with under `config`.
+pub fn tag_with(path: &Path, config: &ScanOptions) -> String {
+    classify_with(path, config).to_string()
+}
+
+/// The tag `path` is classified with by name alone, as by
+/// `--classify-only`.
+pub fn tag_by_name(path: &Path) -> String {
+    let config = ScanOptions {
+        classify_only: true,
+        ..ScanOptions::default()
+    };
+    tag_with(path, &config)
+}
+
+/// Classifies the file at `path` the way the scan does.
+pub fn file_class(path: &Path) -> FileClassification {
+    match classify(path) {
+        EntryClassification::File(class) => class,
+        _ => panic!("`{}` is not classified as a file", path.display()),
+    }
+}
+
+/// Names of the files directly in `dir` that were opened while `f` ran,
+/// seen through inotify, so tests can tell that nothing was read.
+#[cfg(target_os = "linux")]
+pub fn opened_files(dir: &Path, f: impl FnOnce()) -> Vec<String> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    assert!(fd >= 0, "failed to start watching");
+    let name = CString::new(dir.as_os_str().as_bytes()).expect("no NUL in the path");
+    let watch = unsafe { libc::inotify_add_watch(fd, name.as_ptr(), libc::IN_OPEN) };
+    assert!(watch >= 0, "failed to watch `{}`", dir.display());
+
+    f();
+
+    let mut opened = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        let Ok(read) = usize::try_from(read) else {
+            break;
+        };
+        if read == 0 {
+            break;
+        }
+        // each event is a fixed header followed by a padded name
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header <= read {
+            let event = unsafe {
+                buf.as_ptr()
+                    .add(offset)
+                    .cast::<libc::inotify_event>()
+                    .read_unaligned()
+            };
+            let name = &buf[offset + header..offset + header + event.len as usize];
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            if event.mask & libc::IN_ISDIR == 0 && !name.is_empty() {
+                opened.push(String::from_utf8_lossy(name).into_owned());
+            }
+            offset += header + event.len as usize;
+        }
+    }
+    unsafe { libc::close(fd) };
+    opened.sort();
+    opened.dedup();
+    opened
+}
+
+/// An ISO 9660 image holding nothing but a primary volume descriptor
+/// labeled `label`.
+pub fn iso9660_image(label: &str) -> Vec<u8> {
+    let mut image = vec![0; 17 * 2048];
+    let descriptor = &mut image[16 * 2048..];
+    descriptor[0] = 1;
+    descriptor[1..6].copy_from_slice(b"CD001");
+    descriptor[40..72].fill(b' ');
+    descriptor[40..40 + label.len()].copy_from_slice(label.as_bytes());
+    image
+}
+
+/// A Windows 10 `$I` index record of a file of 100 bytes deleted from
+/// `original` at `filetime`, in 100ns steps since 1601.
+pub fn recycle_index(original: &str, filetime: u64) -> Vec<u8> {
+    let name = original.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let mut record = [2u64, 100, filetime]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    record.extend(u32::try_from(name.len()).unwrap().to_le_bytes());
+    record.extend(name.iter().flat_map(|unit| unit.to_le_bytes()));
+    record
+}
+
+/// A JPEG with nothing but EXIF metadata, taken at `taken` as
+/// `YYYY:MM:DD HH:MM:SS` and, if given, at latitude and longitude `gps` in
+/// degrees, minutes and seconds.
+pub fn exif_jpeg(taken: &str, gps: Option<([u32; 3], &str, [u32; 3], &str)>) -> Vec<u8> {
+    use exif::{experimental::Writer, Field, In, Rational, Tag, Value};
+
+    let field = |tag, value| Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value,
+    };
+    let dms =
+        |parts: [u32; 3]| Value::Rational(parts.map(|part| Rational::from((part, 1))).to_vec());
+    let mut fields = vec![field(
+        Tag::DateTimeOriginal,
+        Value::Ascii(vec![taken.as_bytes().to_vec()]),
+    )];
+    if let Some((latitude, north_south, longitude, east_west)) = gps {
+        fields.extend([
+            field(Tag::GPSLatitude, dms(latitude)),
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![north_south.into()])),
+            field(Tag::GPSLongitude, dms(longitude)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![east_west.into()])),
+        ]);
+    }
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer
+        .write(&mut tiff, false)
+        .expect("failed to write the EXIF data");
+
+    // start of image, the APP1 segment holding the EXIF data, end of image
+    let segment = [b"Exif\0\0".as_slice(), tiff.get_ref()].concat();
+    let length = u16::try_from(segment.len() + 2).expect("the segment fits");
+    [
+        [0xFF, 0xD8, 0xFF, 0xE1].as_slice(),
+        &length.to_be_bytes(),
+        &segment,
+        &[0xFF, 0xD9],
+    ]
+    .concat()
+}
+
+/// An ISO 9660 image labeled `label` holding `files`, given by their paths
+/// with at most one folder, each in a sector of its own. Names are stored
+/// with a `;1` version, as mastering tools do.
+pub fn iso9660_with_files(label: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+    const SECTOR: usize = 2048;
+
+    let record = |extent: usize, size: usize, dir: bool, name: &[u8]| {
+        let mut record = vec![0u8; 33 + name.len() + (name.len() + 1) % 2];
+        let extent = u32::try_from(extent).unwrap();
+        let size = u32::try_from(size).unwrap();
+        record[0] = u8::try_from(record.len()).unwrap();
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[25] = if dir { 2 } else { 0 };
+        record[28..30].copy_from_slice(&1u16.to_le_bytes());
+        record[30..32].copy_from_slice(&1u16.to_be_bytes());
+        record[32] = u8::try_from(name.len()).unwrap();
+        record[33..33 + name.len()].copy_from_slice(name);
+        record
+    };
+
+    let mut folders = Vec::new();
+    for (path, _) in files {
+        if let Some((folder, _)) = path.split_once('/') {
+            if !folders.contains(&folder) {
+                folders.push(folder);
+            }
+        }
+    }
+    // the root at 18, then a sector per folder, then one per file
+    let dir_sector = |folder: Option<&str>| {
+        folder.map_or(18, |folder| {
+            19 + folders.iter().position(|&f| f == folder).unwrap()
+        })
+    };
+    let file_sector = |index: usize| 19 + folders.len() + index;
+
+    let mut image = iso9660_image(label);
+    image.resize(file_sector(files.len()) * SECTOR, 0);
+    let root = record(18, SECTOR, true, &[0]);
+    image[16 * SECTOR + 156..16 * SECTOR + 156 + root.len()].copy_from_slice(&root);
+    image[17 * SECTOR] = 255;
+    image[17 * SECTOR + 1..17 * SECTOR + 6].copy_from_slice(b"CD001");
+
+    for folder in iter::once(None).chain(folders.iter().copied().map(Some)) {
+        let mut dir = [
+            record(dir_sector(folder), SECTOR, true, &[0]),
+            record(18, SECTOR, true, &[1]),
+        ]
+        .concat();
+        if folder.is_none() {
+            for &subfolder in &folders {
+                dir.extend(record(
+                    dir_sector(Some(subfolder)),
+                    SECTOR,
+                    true,
+                    subfolder.as_bytes(),
+                ));
+            }
+        }
+        for (index, (path, contents)) in files.iter().enumerate() {
+            let (parent, name) = match path.split_once('/') {
+                Some((parent, name)) => (Some(parent), name),
+                None => (None, *path),
+            };
+            if parent == folder {
+                let name = format!("{};1", name);
+                dir.extend(record(
+                    file_sector(index),
+                    contents.len(),
+                    false,
+                    name.as_bytes(),
+                ));
+            }
+        }
+        let at = dir_sector(folder) * SECTOR;
+        image[at..at + dir.len()].copy_from_slice(&dir);
+    }
+    for (index, (_, contents)) in files.iter().enumerate() {
+        let at = file_sector(index) * SECTOR;
+        image[at..at + contents.len()].copy_from_slice(contents);
+    }
+    image
+}
+
+/// A zip holding `files`, given by their names and contents.
+pub fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(*name, SimpleFileOptions::default())
+            .and_then(|()| Ok(zip.write_all(contents)?))
+            .expect("failed to write the zip");
+    }
+    zip.finish().expect("failed to write the zip").into_inner()
+}
+
+/// A leaf valid until mid 2099, issued by the root after it.
+pub const CERT_CHAIN: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBeDCCAR6gAwIBAgIUQ4v1PjETuH7mCiLA6x+U34Wxi8QwCgYIKoZIzj0EAwIw\n\
+GzEZMBcGA1UEAwwQQmFja3VwIFRlc3QgUm9vdDAgFw0yMDAxMDEwMDAwMDBaGA8y\n\
+MDk5MDYzMDAwMDAwMFowFzEVMBMGA1UEAwwMbGVhZi5leGFtcGxlMFkwEwYHKoZI\n\
+zj0CAQYIKoZIzj0DAQcDQgAEp6YcTx3bpkj12TjF/zf0781+ElOYBGJRGX+ckTiw\n\
+wPagMKP8ivgEPfpLiqk6NgI680Ka4vh9lG0bsTyNaincqaNCMEAwHQYDVR0OBBYE\n\
+FAfQKA+/BMF0zh/2V1Cxs/zjvfmtMB8GA1UdIwQYMBaAFOz0FpnRGNCIfboTJDnJ\n\
+sEHxP+5oMAoGCCqGSM49BAMCA0gAMEUCIQDwLad3+hoqs/CBF8wjaieU1byle7Ww\n\
+sv5blJ/519B8qwIgAr9tXUzwMl8AWNrQMshwuc7/rHuK6rO3IK7el943zYA=\n\
+-----END CERTIFICATE-----\n\
+-----BEGIN CERTIFICATE-----\n\
+MIIBjDCCATOgAwIBAgIUQuiOt5FVDKX+r0CaoZuWVKqJK1wwCgYIKoZIzj0EAwIw\n\
+GzEZMBcGA1UEAwwQQmFja3VwIFRlc3QgUm9vdDAgFw0yMDAxMDEwMDAwMDBaGA8y\n\
+MDk5MTIzMTAwMDAwMFowGzEZMBcGA1UEAwwQQmFja3VwIFRlc3QgUm9vdDBZMBMG\n\
+ByqGSM49AgEGCCqGSM49AwEHA0IABGRCX0RlOWbsvUxyaCBD2bqJOSc/XP87InSS\n\
+/iln7nsSc8Wk90IDm83S+J8DZca6nyfCQ3vCMMr/6uFjzkI+XK6jUzBRMB0GA1Ud\n\
+DgQWBBTs9BaZ0RjQiH26EyQ5ybBB8T/uaDAfBgNVHSMEGDAWgBTs9BaZ0RjQiH26\n\
+EyQ5ybBB8T/uaDAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCIBPL\n\
+dnRW/LhfYUNeMLUE1LedC/gaNdM/YJhd44bvpF1tAiAVMQxilRazr2dD4RkXxWcK\n\
+AAMsgZrS/KEaoXSZyZWplw==\n\
+-----END CERTIFICATE-----\n\
+";
@@ -0,0 +1,126 @@
+use std::{collections::BTreeMap, ffi::OsString};
+
+/// Reported entries of one root, sorted into the directories they are in,
+/// for `--format tree`.
+#[derive(Debug, Default)]
+pub struct ReportTree {
+    children: BTreeMap<OsString, ReportTree>,
+    /// What follows the name when the entry itself was reported, like
+    /// `# secret(netrc) (empty)`.
+    line: Option<String>,
+}
+
+impl ReportTree {
+    /// Adds an entry at `path`, given as its names below the root, creating
+    /// the directories on the way to it.
+    pub fn insert<I>(&mut self, path: I, line: String)
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        let node = path.into_iter().fold(self, |node, name| {
+            node.children.entry(name.into()).or_default()
+        });
+        node.line = Some(line);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// The lines below the root, indented by two spaces per level.
+    /// Directories end with a `/`. With `flatten`, a directory that wasn't
+    /// reported itself and holds nothing but one other directory shares
+    /// its line, so `com/`, `example/` and `app/` become `com/example/app/`.
+    pub fn render(&self, flatten: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.render_children(flatten, 0, &mut lines);
+        lines
+    }
+
+    fn render_children(&self, flatten: bool, depth: usize, lines: &mut Vec<String>) {
+        for (name, child) in &self.children {
+            let mut name = name.to_string_lossy().into_owned();
+            let mut node = child;
+            while flatten && node.line.is_none() && node.children.len() == 1 {
+                let (next_name, next) = node.children.iter().next().expect("one child");
+                if next.children.is_empty() {
+                    break;
+                }
+                name = format!("{}/{}", name, next_name.to_string_lossy());
+                node = next;
+            }
+            if !node.children.is_empty() {
+                name.push('/');
+            }
+            let line = match &node.line {
+                Some(line) => format!("{}{} {}", "  ".repeat(depth), name, line),
+                None => format!("{}{}", "  ".repeat(depth), name),
+            };
+            lines.push(line);
+            node.render_children(flatten, depth + 1, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maven_tree() -> ReportTree {
+        let mut tree = ReportTree::default();
+        for (path, line) in [
+            ("src/main/java/com/example/App.java", "# java"),
+            ("src/main/resources/.env", "# dotenv"),
+            ("README.md", "# markdown"),
+        ] {
+            tree.insert(path.split('/'), line.to_owned());
+        }
+        tree
+    }
+
+    #[test]
+    fn entries_are_indented_below_their_directories() {
+        assert_eq!(
+            maven_tree().render(false),
+            [
+                "README.md # markdown",
+                "src/",
+                "  main/",
+                "    java/",
+                "      com/",
+                "        example/",
+                "          App.java # java",
+                "    resources/",
+                "      .env # dotenv",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_child_chains_are_flattened_onto_one_line() {
+        assert_eq!(
+            maven_tree().render(true),
+            [
+                "README.md # markdown",
+                "src/main/",
+                "  java/com/example/",
+                "    App.java # java",
+                "  resources/",
+                "    .env # dotenv",
+            ]
+        );
+    }
+
+    #[test]
+    fn reported_directories_are_not_flattened_away() {
+        let mut tree = ReportTree::default();
+        tree.insert(["app"], "# node-project".to_owned());
+        tree.insert(["app", "config", ".env"], "# dotenv".to_owned());
+
+        assert_eq!(
+            tree.render(true),
+            ["app/ # node-project", "  config/", "    .env # dotenv"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::VcsFileType;
+
+/// Lists the submodule paths declared in a `.gitmodules` file, relative to
+/// the repository root.
+pub fn parse_gitmodules(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "path").then(|| value.trim().to_owned())
+        })
+        .collect()
+}
+
+/// Reads a `.git` *file*, which a submodule or worktree checkout has in place
+/// of the `.git` directory, pointing at where its repository data lives.
+///
+/// Submodules point into the superproject's `.git/modules`, e.g.
+/// `gitdir: ../.git/modules/lib`, which is how the superproject is found.
+pub fn read_gitlink(path: &Path) -> Option<VcsFileType> {
+    let text = fs::read_to_string(path).ok()?;
+    let target = text.lines().next()?.strip_prefix("gitdir:")?.trim();
+    let checkout = path.parent()?;
+    let target = normalize(&checkout.join(target));
+
+    let components = target.components().collect::<Vec<_>>();
+    let is_git = |c: &Component| c.as_os_str().eq_ignore_ascii_case(".git");
+    let kind = components
+        .windows(2)
+        .position(|pair| is_git(&pair[0]))
+        .map(|index| {
+            (
+                index,
+                components[index + 1].as_os_str().to_ascii_lowercase(),
+            )
+        });
+
+    Some(match kind {
+        Some((index, dir)) if dir == "modules" => VcsFileType::Submodule {
+            superproject: components[..index].iter().collect(),
+        },
+        Some((_, dir)) if dir == "worktrees" => VcsFileType::Worktree,
+        _ => VcsFileType::GitLink,
+    })
+}
+
+/// Resolves `.` and `..` without touching the filesystem, as the target may
+/// not be reachable from where the scan is run.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Whether `path` is the internal data directory of a version control
+/// system, as opposed to a checkout.
+pub fn is_vcs_internal_dir(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case(".git") || name.eq_ignore_ascii_case(".svn"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempTree;
+
+    #[test]
+    fn submodule_paths_are_read_from_gitmodules() {
+        let text =
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git\n\
+                    [submodule \"docs\"]\n\tpath=docs\n";
+        assert_eq!(parse_gitmodules(text), ["vendor/lib", "docs"]);
+        assert!(parse_gitmodules("").is_empty());
+    }
+
+    #[test]
+    fn gitlinks_into_modules_name_their_superproject() {
+        let tree = TempTree::new();
+        let link = tree.file("vendor/lib/.git", "gitdir: ../../.git/modules/vendor/lib\n");
+        assert!(matches!(
+            read_gitlink(&link),
+            Some(VcsFileType::Submodule { superproject }) if superproject == tree.path()
+        ));
+    }
+
+    #[test]
+    fn other_gitlinks_are_told_apart() {
+        let tree = TempTree::new();
+        let worktree = tree.file("wt/.git", "gitdir: /repo/.git/worktrees/wt\n");
+        assert!(matches!(
+            read_gitlink(&worktree),
+            Some(VcsFileType::Worktree)
+        ));
+
+        let separate = tree.file("sep/.git", "gitdir: /elsewhere/sep.git\n");
+        assert!(matches!(
+            read_gitlink(&separate),
+            Some(VcsFileType::GitLink)
+        ));
+
+        let junk = tree.file("junk/.git", "not a link\n");
+        assert!(read_gitlink(&junk).is_none());
+    }
+
+    #[test]
+    fn repository_data_directories_are_internal() {
+        assert!(is_vcs_internal_dir(Path::new("/repo/.git")));
+        assert!(is_vcs_internal_dir(Path::new("/repo/.SVN")));
+        assert!(!is_vcs_internal_dir(Path::new("/repo/src")));
+    }
+}
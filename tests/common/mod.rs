@@ -0,0 +1,64 @@
+//! Scratch trees for the tests running the binary.
+
+// each test binary uses only some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A scratch directory with a `src` tree to back up and an empty settings
+/// file, so the user's own settings don't interfere.
+pub struct Scratch {
+    root: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("backup-bin-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("config.toml"), "").unwrap();
+        fs::write(root.join("src/.env"), "KEY=value\n").unwrap();
+        Self { root }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Writes `contents` to `name` below the `src` tree.
+    pub fn source(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.root.join("src").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Runs a backup of `src` into `dest` with `args`.
+    pub fn output(&self, dest: &Path, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_backup"))
+            .arg("--config")
+            .arg(self.path("config.toml"))
+            .arg("--no-cache")
+            .arg(self.path("src"))
+            .arg("--dest")
+            .arg(dest)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Like [`Self::output`], returning only the exit code.
+    pub fn run(&self, dest: &Path, args: &[&str]) -> i32 {
+        self.output(dest, args).status.code().unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,69 @@
+//! Runs the binary to check the exit codes documented in `main.rs`.
+
+mod common;
+
+use common::Scratch;
+use std::fs;
+use walkdir::WalkDir;
+
+#[test]
+fn complete_backup_exits_with_zero() {
+    let scratch = Scratch::new("ok");
+    assert_eq!(scratch.run(&scratch.path("dest"), &[]), 0);
+    assert_eq!(scratch.run(&scratch.path("dest"), &["--verify"]), 0);
+}
+
+#[test]
+fn unusable_destination_exits_with_three() {
+    let scratch = Scratch::new("dest");
+    let dest = scratch.path("dest");
+    fs::write(&dest, "a file where the folder should be").unwrap();
+    assert_eq!(scratch.run(&dest, &[]), 3);
+}
+
+#[test]
+fn changed_copies_fail_verification_with_five() {
+    let scratch = Scratch::new("verify");
+    let dest = scratch.path("dest");
+    assert_eq!(scratch.run(&dest, &[]), 0);
+
+    let copy = WalkDir::new(&dest)
+        .into_iter()
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name() == ".env")
+        .unwrap();
+    let copy = copy.path();
+    fs::write(copy, "KEY=changed\n").unwrap();
+    assert_eq!(scratch.run(&dest, &["--verify"]), 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_files_are_skipped_with_two() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new("skip");
+    let source = scratch.path("src/.env");
+    fs::set_permissions(&source, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read(&source).is_ok() {
+        // permissions don't keep root out
+        eprintln!("skipped, `{}` is still readable", source.display());
+        return;
+    }
+    assert_eq!(scratch.run(&scratch.path("dest"), &[]), 2);
+}
+
+#[test]
+fn used_up_time_budget_exits_with_four() {
+    let scratch = Scratch::new("budget");
+    assert_eq!(
+        scratch.run(&scratch.path("dest"), &["--max-runtime", "0s"]),
+        4
+    );
+}
+
+#[test]
+fn verification_after_the_backup_passes_for_intact_copies() {
+    let scratch = Scratch::new("verify-after");
+    assert_eq!(scratch.run(&scratch.path("dest"), &["--verify-after"]), 0);
+}
@@ -0,0 +1,50 @@
+//! Drives a scan through the library with an observer, the way a UI or
+//! service would.
+
+mod common;
+
+use backup::{scan_with_observer, EntryClassification, ScanObserver, ScanOptions};
+use common::Scratch;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+struct Findings {
+    entries: Vec<(PathBuf, String)>,
+    files: u64,
+}
+
+impl ScanObserver for Findings {
+    fn on_entry(&mut self, classification: &EntryClassification, path: &Path) {
+        self.entries
+            .push((path.to_path_buf(), classification.to_string()));
+    }
+
+    fn on_progress(&mut self, files: u64, _bytes: u64) {
+        self.files = files;
+    }
+}
+
+#[test]
+fn observers_hear_of_what_the_scan_finds() {
+    let scratch = Scratch::new("observed");
+    scratch.source("app/.env", "KEY=value\n");
+    scratch.source("notes.txt", "plain text");
+    let root = scratch.path("src");
+    let config = ScanOptions::builder().jobs(2).build();
+
+    let mut findings = Findings::default();
+    scan_with_observer(&root, &config, &mut findings).unwrap();
+
+    // regular files and folders are left out
+    findings.entries.sort();
+    assert_eq!(
+        findings.entries,
+        [
+            (root.join(".env"), "dotenv".to_owned()),
+            (root.join("app").join(".env"), "dotenv".to_owned()),
+        ]
+    );
+    assert_eq!(findings.files, 3);
+    // nothing is written next to the files, no cache and no copies
+    assert!(!scratch.path("out").exists());
+}
@@ -0,0 +1,47 @@
+//! Runs the binary to check that `--show-regular` lists what it saw.
+
+mod common;
+
+use common::Scratch;
+use walkdir::WalkDir;
+
+#[test]
+fn regular_files_are_listed_only_with_show_regular() {
+    let scratch = Scratch::new("show-regular");
+    let plain = scratch.source("data/readings.xyz", "1 2 3\n");
+    scratch.source("data/empty.xyz", "");
+    let dest = scratch.path("dest");
+    let line = format!("{} # regular", plain.display());
+
+    let stdout = String::from_utf8(scratch.output(&dest, &[]).stdout).unwrap();
+    assert!(!stdout.contains("readings.xyz"), "{}", stdout);
+
+    let output = scratch.output(&dest, &["--show-regular"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|printed| printed == line), "{}", stdout);
+    assert!(stdout.contains(".env # dotenv"), "{}", stdout);
+    assert!(stdout.contains("empty.xyz # regular"), "{}", stdout);
+    // directories are walked through, not listed
+    assert!(
+        !stdout.lines().any(|printed| printed.contains("data #")),
+        "{}",
+        stdout
+    );
+    // listing them doesn't mean copying them
+    let copied = WalkDir::new(&dest)
+        .into_iter()
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name() == "readings.xyz");
+    assert!(!copied);
+
+    let output = scratch.output(&dest, &["--show-regular", "--format", "json"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let record = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|record| record["path"] == plain.to_str().unwrap())
+        .expect("the plain file is listed");
+    assert_eq!(record["tag"], "regular");
+    assert_eq!(record["category"], serde_json::Value::Null);
+}
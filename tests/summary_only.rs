@@ -0,0 +1,26 @@
+//! Runs the binary to check what `--summary-only` prints.
+
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn only_the_category_table_is_printed() {
+    let scratch = Scratch::new("summary");
+    scratch.source("docs/report.csv", "a,b\n1,2\n");
+    scratch.source("docs/notes.txt", "plain text");
+    let dest = scratch.path("dest");
+
+    let output = scratch.output(&dest, &["--summary-only"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with("category"), "{}", stdout);
+    assert!(lines.last().unwrap().starts_with("total"), "{}", stdout);
+    assert_eq!(lines.len(), 4, "{}", stdout);
+    assert!(!stdout.contains(" # "), "{}", stdout);
+
+    // without it, every file is listed
+    let stdout = String::from_utf8(scratch.output(&dest, &[]).stdout).unwrap();
+    assert_eq!(stdout.matches(" # ").count(), 2, "{}", stdout);
+}
@@ -0,0 +1,75 @@
+//! Runs the binary to check what `--format tree` prints.
+
+mod common;
+
+use common::Scratch;
+use walkdir::WalkDir;
+
+#[test]
+fn single_child_chains_are_collapsed_in_tree_output() {
+    let scratch = Scratch::new("tree");
+    scratch.source("src/main/java/com/example/.env", "TOKEN=1\n");
+    scratch.source("src/main/resources/report.csv", "a,b\n1,2\n");
+    let dest = scratch.path("dest");
+
+    let output = scratch.output(&dest, &["--format", "tree", "--flatten-single-child-dirs"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[1..],
+        [
+            ".env # dotenv",
+            "src/main/",
+            "  java/com/example/",
+            "    .env # dotenv",
+            "  resources/",
+            "    report.csv # csv(',')",
+        ],
+        "{}",
+        stdout
+    );
+
+    // what is copied doesn't depend on how it's shown
+    let copied = WalkDir::new(&dest)
+        .into_iter()
+        .filter_map(Result::ok)
+        .any(|entry| entry.path().ends_with("src/main/java/com/example/.env"));
+    assert!(copied);
+
+    let stdout = String::from_utf8(scratch.output(&dest, &["--format", "tree"]).stdout).unwrap();
+    assert!(
+        stdout.contains("\n      com/\n        example/\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn breadth_first_walks_nest_the_tree_the_same() {
+    let scratch = Scratch::new("tree-bfs");
+    scratch.source("app/config/.env", "TOKEN=1\n");
+    scratch.source("app/data.csv", "a,b\n1,2\n");
+    let dest = scratch.path("dest");
+
+    let tree = |args: &[&str]| {
+        let output = scratch.output(&dest, &[&["--format", "tree"], args].concat());
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let stdout = tree(&["--traversal", "bfs"]);
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[1..],
+        [
+            ".env # dotenv",
+            "app/",
+            "  config/",
+            "    .env # dotenv",
+            "  data.csv # csv(',')",
+        ],
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout, tree(&[]));
+}
@@ -0,0 +1,37 @@
+//! Runs the binary with `--use-vss`, which takes real shadow copies and so
+//! only runs as an administrator on Windows, with the `vss-admin-tests`
+//! feature: `cargo test --features vss-admin-tests --test vss`.
+
+#![cfg(all(windows, feature = "vss-admin-tests"))]
+
+mod common;
+
+use common::Scratch;
+use std::{fs, os::windows::fs::OpenOptionsExt};
+use walkdir::WalkDir;
+
+#[test]
+fn files_locked_by_another_process_are_copied_from_a_shadow_copy() {
+    let scratch = Scratch::new("vss");
+    let locked = scratch.source("profile/.env", "KEY=locked\n");
+    // shared with nobody, like a hive or an open database
+    let _open = fs::OpenOptions::new()
+        .read(true)
+        .share_mode(0)
+        .open(&locked)
+        .unwrap();
+    let dest = scratch.path("dest");
+
+    let output = scratch.output(&dest, &["--use-vss"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let copy = WalkDir::new(&dest)
+        .into_iter()
+        .filter_map(Result::ok)
+        .find(|entry| entry.path().ends_with("profile/.env"))
+        .expect("the locked file is copied");
+    assert_eq!(fs::read_to_string(copy.path()).unwrap(), "KEY=locked\n");
+}